@@ -0,0 +1,90 @@
+use bevy::prelude::*;
+use bevy_nokhwa::camera::BackgroundCamera;
+use bevy_nokhwa::feed::CameraFeed;
+use bevy_nokhwa::nokhwa::utils::{ApiBackend, Resolution};
+use bevy_nokhwa::selector::CameraSelector;
+use bevy_nokhwa::source::TestPattern;
+use bevy_nokhwa::BevyNokhwaPlugin;
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                title: "Feed Texture".to_string(),
+                resolution: [1280., 720.].into(),
+                ..default()
+            }),
+            ..default()
+        }))
+        .add_plugins(BevyNokhwaPlugin)
+        .add_systems(Startup, setup)
+        .add_systems(Update, rotate)
+        .run();
+}
+
+#[derive(Component)]
+struct Rotate;
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    // frames are written into this image, anything using it shows the live feed
+    let feed = images.add(Image::default());
+
+    commands.spawn((
+        BackgroundCamera::select(
+            ApiBackend::Auto,
+            [
+                CameraSelector::Any,
+                CameraSelector::TestPattern {
+                    pattern: TestPattern::Gradient,
+                    resolution: Resolution::new(640, 480),
+                    frame_rate: 30,
+                },
+            ],
+            None,
+        )
+        .unwrap(),
+        CameraFeed {
+            image: feed.clone(),
+        },
+    ));
+
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(2.0, 2.0, 2.0))),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color_texture: Some(feed.clone()),
+            unlit: true,
+            ..default()
+        })),
+        Rotate,
+    ));
+
+    commands.spawn((
+        Mesh3d(meshes.add(Rectangle::new(1.6, 1.2))),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color_texture: Some(feed),
+            unlit: true,
+            ..default()
+        })),
+        Transform::from_xyz(-2.5, 1.0, 0.0),
+    ));
+
+    commands.spawn((
+        Camera3d::default(),
+        Camera {
+            clear_color: ClearColorConfig::Custom(Color::srgb(0.1, 0.1, 0.1)),
+            ..default()
+        },
+        Transform::from_xyz(0.0, 2.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+}
+
+fn rotate(time: Res<Time>, mut query: Query<&mut Transform, With<Rotate>>) {
+    for mut transform in query.iter_mut() {
+        transform.rotate_y(time.delta_secs() * 0.5);
+    }
+}
@@ -2,9 +2,12 @@ use bevy::prelude::*;
 use nokhwa::utils::CameraIndex;
 
 use bevy_nokhwa::camera::BackgroundCamera;
+use bevy_nokhwa::frame::CameraOrientation;
 use bevy_nokhwa::nokhwa::utils::ApiBackend;
 use bevy_nokhwa::nokhwa::utils::FrameFormat;
 use bevy_nokhwa::nokhwa::utils::{CameraFormat, RequestedFormatType, Resolution};
+use bevy_nokhwa::selector::CameraSelector;
+use bevy_nokhwa::source::TestPattern;
 use bevy_nokhwa::BevyNokhwaPlugin;
 
 fn main() {
@@ -18,7 +21,7 @@ fn main() {
             ..default()
         }))
         .add_plugins(BevyNokhwaPlugin)
-        .add_plugins(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default())
+        .add_plugins(bevy::diagnostic::FrameTimeDiagnosticsPlugin)
         .add_plugins(bevy::diagnostic::LogDiagnosticsPlugin::default())
         .add_systems(Startup, setup_camera)
         .run();
@@ -33,13 +36,26 @@ fn setup_camera(
         .spawn((
             Camera3d::default(),
             Transform::from_xyz(-2.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+            // show the webcam like a mirror
+            CameraOrientation {
+                mirror: true,
+                ..default()
+            },
         ))
         // auto find camera and use the highest resolution
         // .insert(BackgroundCamera::auto())
         .insert(
-            BackgroundCamera::new(
+            BackgroundCamera::select(
                 ApiBackend::Auto,
-                Some(CameraIndex::Index(0)),
+                [
+                    CameraSelector::Index(CameraIndex::Index(0)),
+                    // show color bars when no camera is attached
+                    CameraSelector::TestPattern {
+                        pattern: TestPattern::ColorBars,
+                        resolution: Resolution::new(640, 480),
+                        frame_rate: 30,
+                    },
+                ],
                 Some(RequestedFormatType::Closest(CameraFormat::new(
                     Resolution::new(640, 480),
                     FrameFormat::MJPEG,
@@ -0,0 +1,103 @@
+use bevy::prelude::*;
+use bevy_nokhwa::camera::{BackgroundCamera, NegotiatedFormat};
+use bevy_nokhwa::frame::CameraOrientation;
+use bevy_nokhwa::nokhwa::utils::{RequestedFormatType, Resolution};
+use bevy_nokhwa::source::TestPattern;
+use bevy_nokhwa::{BackgroundFit, BevyNokhwaPlugin};
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                title: "Test Pattern".to_string(),
+                resolution: [1280., 720.].into(),
+                ..default()
+            }),
+            ..default()
+        }))
+        .add_plugins(BevyNokhwaPlugin)
+        .add_systems(Startup, setup_camera)
+        .add_systems(Update, (cycle_pattern, cycle_resolution, cycle_fit, orient))
+        .run();
+}
+
+fn setup_camera(mut commands: Commands) {
+    commands.spawn((
+        Camera3d::default(),
+        BackgroundFit::Contain {
+            letterbox: Some(Color::BLACK),
+        },
+        BackgroundCamera::test_pattern(TestPattern::ColorBars, Resolution::new(1280, 720), 30),
+    ));
+}
+
+/// press space to switch between patterns
+fn cycle_pattern(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut camera: Query<&mut BackgroundCamera>,
+    mut pattern: Local<TestPattern>,
+) {
+    if !keys.just_pressed(KeyCode::Space) {
+        return;
+    }
+
+    *pattern = match *pattern {
+        TestPattern::ColorBars => TestPattern::Gradient,
+        TestPattern::Gradient => TestPattern::Checkerboard,
+        TestPattern::Checkerboard => TestPattern::ColorBars,
+    };
+
+    for mut camera in camera.iter_mut() {
+        *camera = BackgroundCamera::test_pattern(*pattern, Resolution::new(1280, 720), 30);
+    }
+}
+
+/// press R to switch between resolutions without reopening the camera
+fn cycle_resolution(
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Query<(&BackgroundCamera, &NegotiatedFormat)>,
+) {
+    if !keys.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+
+    for (camera, format) in camera.iter() {
+        let resolution = match format.0.map(|format| format.resolution()) {
+            Some(resolution) if resolution.width() == 1280 => Resolution::new(640, 360),
+            Some(resolution) if resolution.width() == 640 => Resolution::new(320, 180),
+            _ => Resolution::new(1280, 720),
+        };
+        camera.set_format(RequestedFormatType::HighestResolution(resolution));
+    }
+}
+
+/// press F to switch between the ways the pattern is fitted into the window
+fn cycle_fit(keys: Res<ButtonInput<KeyCode>>, mut views: Query<&mut BackgroundFit>) {
+    if !keys.just_pressed(KeyCode::KeyF) {
+        return;
+    }
+
+    for mut fit in views.iter_mut() {
+        *fit = match *fit {
+            BackgroundFit::Stretch => BackgroundFit::Contain {
+                letterbox: Some(Color::BLACK),
+            },
+            BackgroundFit::Contain { .. } => BackgroundFit::Cover,
+            BackgroundFit::Cover => BackgroundFit::ActualSize,
+            BackgroundFit::ActualSize => BackgroundFit::Rect(Rect::new(0.05, 0.05, 0.35, 0.35)),
+            BackgroundFit::Rect(_) => BackgroundFit::Stretch,
+        };
+    }
+}
+
+/// press M to mirror the pattern and T to turn it clockwise
+fn orient(keys: Res<ButtonInput<KeyCode>>, mut cameras: Query<&mut CameraOrientation>) {
+    for mut orientation in cameras.iter_mut() {
+        if keys.just_pressed(KeyCode::KeyM) {
+            orientation.mirror = !orientation.mirror;
+        }
+        if keys.just_pressed(KeyCode::KeyT) {
+            orientation.rotation = orientation.rotation.clockwise();
+        }
+    }
+}
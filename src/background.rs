@@ -1,37 +1,45 @@
 use crate::camera::BackgroundCamera;
+use crate::frame::{CameraFrame, CameraOrientation, PooledImage};
+use bevy::core_pipeline::core_3d::CORE_3D_DEPTH_FORMAT;
 use bevy::prelude::*;
-use bevy::render::extract_resource::ExtractResource;
-use bevy::render::render_graph::{Node, RenderLabel, RenderSubGraph};
+use bevy::render::extract_component::ExtractComponent;
+use bevy::render::render_graph::{Node, RenderLabel};
 use bevy::render::render_graph::{NodeRunError, RenderGraphContext, SlotInfo};
 use bevy::render::render_resource::{
-    AddressMode, BindGroup, BindGroupEntries, BindGroupLayoutEntry, BindingType, BlendComponent,
-    BlendState, Buffer, BufferAddress, BufferInitDescriptor, BufferUsages, ColorTargetState,
-    ColorWrites, Extent3d, Face, FilterMode, FrontFace, ImageCopyTexture, ImageDataLayout,
-    IndexFormat, MultisampleState, Origin3d, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
-    PrimitiveTopology, RawFragmentState, RawRenderPipelineDescriptor, RawVertexBufferLayout,
-    RawVertexState, RenderPassDescriptor, RenderPipeline, SamplerBindingType, SamplerDescriptor,
-    ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureAspect, TextureDescriptor,
-    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor,
-    TextureViewDimension, VertexAttribute, VertexFormat, VertexStepMode,
+    AddressMode, BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType,
+    BlendComponent, BlendState, Buffer, BufferAddress, BufferInitDescriptor, BufferUsages,
+    CachedRenderPipelineId, ColorTargetState, ColorWrites, CompareFunction, DepthStencilState,
+    Extent3d, Face, FilterMode, FragmentState, FrontFace, ImageCopyTexture, ImageDataLayout,
+    IndexFormat, MultisampleState, Origin3d, PipelineCache, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPassDescriptor, RenderPipelineDescriptor, Sampler, SamplerBindingType,
+    SamplerDescriptor, ShaderStages, SpecializedRenderPipeline, SpecializedRenderPipelines,
+    StoreOp, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension, VertexAttribute,
+    VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
 };
 use bevy::render::renderer::{RenderContext, RenderDevice, RenderQueue};
-use bevy::render::view::{ExtractedView, ViewTarget};
-use image::RgbaImage;
+use bevy::render::sync_world::MainEntity;
+use bevy::render::view::{ExtractedView, ViewDepthTexture, ViewTarget};
+use bevy::render::Extract;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Weak};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
+    /// Drawn instead of the camera image where alpha is 1, used for letterboxing.
+    fill: [f32; 4],
 }
 
 impl Vertex {
-    fn desc<'a>() -> RawVertexBufferLayout<'a> {
+    fn desc() -> VertexBufferLayout {
         use std::mem;
-        RawVertexBufferLayout {
+        VertexBufferLayout {
             array_stride: mem::size_of::<Vertex>() as BufferAddress,
             step_mode: VertexStepMode::Vertex,
-            attributes: &[
+            attributes: vec![
                 VertexAttribute {
                     offset: 0,
                     shader_location: 0,
@@ -42,59 +50,137 @@ impl Vertex {
                     shader_location: 1,
                     format: VertexFormat::Float32x2,
                 },
+                VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as BufferAddress,
+                    shader_location: 2,
+                    format: VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
 
-#[derive(Deref, DerefMut, Default, Resource, ExtractResource, Clone)]
-pub struct BackgroundImage(pub RgbaImage);
-
-const VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [-1.0, -1.0, 0.0],
-        tex_coords: [0.0, 1.0],
-    }, // A
-    Vertex {
-        position: [1.0, -1.0, 0.0],
-        tex_coords: [1.0, 1.0],
-    }, // B
-    Vertex {
-        position: [-1.0, 1.0, 0.0],
-        tex_coords: [0.0, 0.0],
-    }, // C
-    Vertex {
-        position: [1.0, 1.0, 0.0],
-        tex_coords: [1.0, 0.0],
-    }, // d
-];
-
-const INDICES: &[u16] = &[0, 1, 2, 2, 1, 3];
-
-#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderSubGraph)]
-pub struct BackgroundGraph;
+/// The latest frame of the [`BackgroundCamera`] on the same entity, `None` until the first one
+/// arrives.
+#[derive(Component, Deref, DerefMut, Default, Clone)]
+pub struct BackgroundImage(pub Option<CameraFrame>);
+
+/// Put on a view to show the [`BackgroundCamera`] on another entity as its background.
+///
+/// Only views with it or with a [`BackgroundCamera`] of their own draw a background, minimaps,
+/// UI and render to texture cameras are left alone.
+#[derive(Component, ExtractComponent, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShowCameraBackground(pub Entity);
+
+/// How the camera image is fitted into the viewport of a view, put it on the view.
+///
+/// Views without it stretch the image over the whole viewport.
+#[derive(Component, ExtractComponent, Debug, Copy, Clone, Default, PartialEq)]
+pub enum BackgroundFit {
+    #[default]
+    Stretch,
+    /// Show the whole image keeping its aspect ratio, and fill the rest of the viewport with
+    /// `letterbox` if there is one. Its alpha is ignored.
+    Contain { letterbox: Option<Color> },
+    /// Fill the viewport keeping the aspect ratio, cropping the image.
+    Cover,
+    /// One image pixel per viewport pixel, centered.
+    ActualSize,
+    /// Stretch the image over a part of the viewport, `(0, 0)` is the top left and `(1, 1)` the
+    /// bottom right corner.
+    Rect(Rect),
+}
+
+impl BackgroundFit {
+    /// Where the image goes in normalized device coordinates, and the part of it that is shown.
+    fn layout(&self, image: UVec2, viewport: UVec2) -> (Rect, Rect) {
+        let full = Rect::new(-1.0, -1.0, 1.0, 1.0);
+        let whole_image = Rect::new(0.0, 0.0, 1.0, 1.0);
+        let image = image.as_vec2().max(Vec2::ONE);
+        let viewport = viewport.as_vec2().max(Vec2::ONE);
+
+        match self {
+            BackgroundFit::Stretch => (full, whole_image),
+            BackgroundFit::Contain { .. } => {
+                let scale = (viewport / image).min_element();
+                let half_size = image * scale / viewport;
+                (
+                    Rect::from_center_half_size(Vec2::ZERO, half_size),
+                    whole_image,
+                )
+            }
+            BackgroundFit::Cover => {
+                let scale = (viewport / image).max_element();
+                let visible = viewport / (image * scale);
+                (full, Rect::from_center_size(Vec2::splat(0.5), visible))
+            }
+            BackgroundFit::ActualSize => (
+                Rect::from_center_half_size(Vec2::ZERO, image / viewport),
+                whole_image,
+            ),
+            BackgroundFit::Rect(rect) => (
+                Rect::new(
+                    rect.min.x * 2.0 - 1.0,
+                    1.0 - rect.max.y * 2.0,
+                    rect.max.x * 2.0 - 1.0,
+                    1.0 - rect.min.y * 2.0,
+                ),
+                whole_image,
+            ),
+        }
+    }
+
+    fn letterbox(&self) -> Option<LinearRgba> {
+        match self {
+            BackgroundFit::Contain {
+                letterbox: Some(color),
+            } => Some(color.to_linear().with_alpha(1.0)),
+            _ => None,
+        }
+    }
+}
+
+/// Four vertices covering `position` with the `uv` part of the oriented image, wound like the
+/// indices expect.
+fn quad(position: Rect, uv: Rect, orientation: CameraOrientation, fill: LinearRgba) -> [Vertex; 4] {
+    let fill = fill.to_f32_array();
+    let vertex = |x: f32, y: f32, u: f32, v: f32| {
+        let (u, v) = orientation.source_uv(u, v);
+        Vertex {
+            position: [x, y, 0.0],
+            tex_coords: [u, v],
+            fill,
+        }
+    };
+    // texture coordinates run top to bottom, a depth of 0 is the far plane
+    [
+        vertex(position.min.x, position.min.y, uv.min.x, uv.max.y),
+        vertex(position.max.x, position.min.y, uv.max.x, uv.max.y),
+        vertex(position.min.x, position.max.y, uv.min.x, uv.min.y),
+        vertex(position.max.x, position.max.y, uv.max.x, uv.min.y),
+    ]
+}
+
+/// The letterbox quad, then the image quad.
+const INDICES: &[u16] = &[0, 1, 2, 2, 1, 3, 4, 5, 6, 6, 5, 7];
+const IMAGE_INDICES: std::ops::Range<u32> = 6..12;
+
 #[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
 pub(crate) struct BackgroundNodeLabel;
 
+pub(crate) const BACKGROUND_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(0x6b1f_2c4e_9a3d_4e7b_8c51_d2f0_a7e3_9b64);
+
 #[derive(Resource)]
 pub struct BackgroundPipeline {
-    render_pipeline: RenderPipeline,
+    texture_bind_group_layout: BindGroupLayout,
+    sampler: Sampler,
 }
 
 impl FromWorld for BackgroundPipeline {
     fn from_world(world: &mut World) -> Self {
-        let mut query = world.query_filtered::<&Msaa, (With<Camera>, With<BackgroundCamera>)>();
-        let msaa = match query.get_single(world) {
-            Ok(m) => *m,
-            Err(_) => Msaa::Sample4,
-        };
         let device = world.resource::<RenderDevice>();
 
-        let shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("Webcam Shader"),
-            source: ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
-
         let texture_bind_group_layout = device.create_bind_group_layout(
             "webcam_bind_group_layout",
             &[
@@ -117,27 +203,55 @@ impl FromWorld for BackgroundPipeline {
             ],
         );
 
-        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("Webcam Render Pipeline Layout"),
-            bind_group_layouts: &[&texture_bind_group_layout],
-            push_constant_ranges: &[],
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
         });
 
-        let render_pipeline = device.create_render_pipeline(&RawRenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: RawVertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                compilation_options: Default::default(),
-                buffers: &[Vertex::desc()],
+        Self {
+            texture_bind_group_layout,
+            sampler,
+        }
+    }
+}
+
+/// What the pipeline has to match in the render pass of a view.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BackgroundPipelineKey {
+    pub samples: u32,
+    pub hdr: bool,
+    pub format: TextureFormat,
+}
+
+impl SpecializedRenderPipeline for BackgroundPipeline {
+    type Key = BackgroundPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        RenderPipelineDescriptor {
+            label: Some(if key.hdr {
+                "Webcam HDR Render Pipeline".into()
+            } else {
+                "Webcam Render Pipeline".into()
+            }),
+            layout: vec![self.texture_bind_group_layout.clone()],
+            push_constant_ranges: Vec::new(),
+            vertex: VertexState {
+                shader: BACKGROUND_SHADER_HANDLE,
+                shader_defs: Vec::new(),
+                entry_point: "vs_main".into(),
+                buffers: vec![Vertex::desc()],
             },
-            fragment: Some(RawFragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                compilation_options: Default::default(),
-                targets: &[Some(ColorTargetState {
-                    format: TextureFormat::bevy_default(),
+            fragment: Some(FragmentState {
+                shader: BACKGROUND_SHADER_HANDLE,
+                shader_defs: Vec::new(),
+                entry_point: "fs_main".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: key.format,
                     blend: Some(BlendState {
                         color: BlendComponent::REPLACE,
                         alpha: BlendComponent::REPLACE,
@@ -157,52 +271,39 @@ impl FromWorld for BackgroundPipeline {
                 // Requires Features::CONSERVATIVE_RASTERIZATION
                 conservative: false,
             },
-            depth_stencil: None,
+            // only fill in what no opaque geometry was drawn over, the 2D depth format is the same
+            depth_stencil: Some(DepthStencilState {
+                format: CORE_3D_DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: default(),
+                bias: default(),
+            }),
             multisample: MultisampleState {
-                count: msaa.samples(),
+                count: key.samples,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
-            // If the pipeline will be used with a multiview render pass, this
-            // indicates how many array layers the attachments will have.
-            multiview: None,
-            cache: None,
-        });
-
-        Self { render_pipeline }
-    }
-}
-
-pub struct BackgroundPassDriverNode;
-
-impl Node for BackgroundPassDriverNode {
-    fn run(
-        &self,
-        graph: &mut RenderGraphContext,
-        _render_context: &mut RenderContext,
-        _world: &World,
-    ) -> Result<(), NodeRunError> {
-        graph.run_sub_graph(BackgroundGraph, vec![], Some(graph.view_entity()))?;
-
-        Ok(())
+            zero_initialize_workgroup_memory: false,
+        }
     }
 }
 
 pub struct BackgroundNode {
-    query: QueryState<&'static ViewTarget, With<ExtractedView>>,
-    vertex_buffer: Option<Buffer>,
+    query: QueryState<(
+        Entity,
+        &'static ViewTarget,
+        &'static ViewDepthTexture,
+        &'static ExtractedView,
+    )>,
     index_buffer: Option<Buffer>,
-    diffuse_bind_group: Option<BindGroup>,
 }
 
 impl BackgroundNode {
     pub fn new(world: &mut World) -> Self {
         Self {
             query: QueryState::new(world),
-
-            vertex_buffer: None,
             index_buffer: None,
-            diffuse_bind_group: None,
         }
     }
 }
@@ -214,34 +315,172 @@ impl Node for BackgroundNode {
 
     fn update(&mut self, world: &mut World) {
         self.query.update_archetypes(world);
-        if let Some(img) = world.get_resource::<BackgroundImage>() {
-            let device = world.get_resource::<RenderDevice>().unwrap();
-            let queue = world.get_resource::<RenderQueue>().unwrap();
-
-            if self.index_buffer.is_none() {
-                let index_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
-                    label: Some("Index Buffer"),
-                    contents: bytemuck::cast_slice(INDICES),
-                    usage: BufferUsages::INDEX,
-                });
-                self.index_buffer = Some(index_buffer)
+        let device = world.get_resource::<RenderDevice>().unwrap();
+
+        if self.index_buffer.is_none() {
+            let index_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("Index Buffer"),
+                contents: bytemuck::cast_slice(INDICES),
+                usage: BufferUsages::INDEX,
+            });
+            self.index_buffer = Some(index_buffer)
+        }
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let textures = world.resource::<BackgroundTextures>();
+        let backgrounds = world.resource::<ViewBackgrounds>();
+
+        // the graph runs once per view, only draw the one it runs for
+        let Ok((entity, target, depth, view)) = self.query.get_manual(world, graph.view_entity())
+        else {
+            return Ok(());
+        };
+        let Some(background) = backgrounds.get(&entity) else {
+            return Ok(());
+        };
+        let Some(texture) = textures.get(&background.camera) else {
+            return Ok(());
+        };
+
+        let Some(pipeline) = world
+            .resource::<PipelineCache>()
+            .get_render_pipeline(background.pipeline)
+        else {
+            return Ok(());
+        };
+        let pass_descriptor = RenderPassDescriptor {
+            label: Some("background_pass"),
+            color_attachments: &[Some(target.get_color_attachment())],
+            depth_stencil_attachment: Some(depth.get_attachment(StoreOp::Store)),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        };
+
+        if let Some(index_buffer) = &self.index_buffer {
+            let mut render_pass = render_context
+                .command_encoder()
+                .begin_render_pass(&pass_descriptor);
+
+            let viewport = view.viewport.as_vec4();
+            render_pass.set_viewport(viewport.x, viewport.y, viewport.z, viewport.w, 0.0, 1.0);
+            render_pass.set_pipeline(pipeline);
+
+            render_pass.set_bind_group(0, &texture.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, *background.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(*index_buffer.slice(..), IndexFormat::Uint16);
+
+            let indices = if background.letterbox {
+                0..IMAGE_INDICES.end
+            } else {
+                IMAGE_INDICES
+            };
+            render_pass.draw_indexed(indices, 0, 0..1);
+        }
+
+        Ok(())
+    }
+}
+
+/// Latest frames of every [`BackgroundCamera`], keyed by its main world entity.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ExtractedBackgroundImages(BTreeMap<Entity, ExtractedBackgroundImage>);
+
+pub struct ExtractedBackgroundImage {
+    frame: CameraFrame,
+    orientation: CameraOrientation,
+}
+
+pub struct BackgroundTexture {
+    texture: Texture,
+    bind_group: BindGroup,
+    /// Pixels of the frame currently in the texture. Sequence numbers restart with every
+    /// camera, so frames are told apart by their buffer.
+    image: Weak<PooledImage>,
+}
+
+/// GPU textures of every camera, created once and recreated only when the frame size changes.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct BackgroundTextures(BTreeMap<Entity, BackgroundTexture>);
+
+pub struct ViewBackground {
+    /// Main world entity of the camera shown.
+    camera: Entity,
+    pipeline: CachedRenderPipelineId,
+    vertex_buffer: Buffer,
+    letterbox: bool,
+}
+
+/// What every view draws as its background, keyed by the view's render world entity.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ViewBackgrounds(BTreeMap<Entity, ViewBackground>);
+
+pub fn extract_background_images(
+    mut extracted: ResMut<ExtractedBackgroundImages>,
+    images: Extract<Query<(Entity, &BackgroundImage, Option<&CameraOrientation>)>>,
+) {
+    extracted.retain(|entity, _| images.contains(*entity));
+    for (entity, image, orientation) in images.iter() {
+        let Some(frame) = &image.0 else {
+            continue;
+        };
+        let orientation = orientation.copied().unwrap_or_default();
+        match extracted.get_mut(&entity) {
+            // only copy frames the render world has not seen yet
+            Some(extracted) if Arc::ptr_eq(&extracted.frame.image, &frame.image) => {
+                extracted.orientation = orientation;
             }
-            if self.vertex_buffer.is_none() {
-                let vertex_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
-                    label: Some("Vertex Buffer"),
-                    contents: bytemuck::cast_slice(VERTICES),
-                    usage: BufferUsages::VERTEX,
-                });
-                self.vertex_buffer = Some(vertex_buffer)
+            _ => {
+                extracted.insert(
+                    entity,
+                    ExtractedBackgroundImage {
+                        frame: frame.clone(),
+                        orientation,
+                    },
+                );
             }
+        }
+    }
+}
 
-            let dimensions = img.0.dimensions();
+pub fn prepare_background_textures(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    pipeline: Res<BackgroundPipeline>,
+    images: Res<ExtractedBackgroundImages>,
+    mut textures: ResMut<BackgroundTextures>,
+) {
+    textures.retain(|entity, _| images.contains_key(entity));
+
+    for (entity, ExtractedBackgroundImage { frame, .. }) in images.iter() {
+        if textures
+            .get(entity)
+            .is_some_and(|texture| texture.image.ptr_eq(&Arc::downgrade(&frame.image)))
+        {
+            continue;
+        }
 
-            let size = Extent3d {
-                width: dimensions.0,
-                height: dimensions.1,
-                depth_or_array_layers: 1,
-            };
+        let dimensions = frame.image.dimensions();
+        // wgpu rejects empty textures, custom sources may still deliver empty frames
+        if dimensions.0 == 0 || dimensions.1 == 0 {
+            continue;
+        }
+
+        let size = Extent3d {
+            width: dimensions.0,
+            height: dimensions.1,
+            depth_or_array_layers: 1,
+        };
+
+        if textures
+            .get(entity)
+            .is_none_or(|texture| texture.texture.size() != size)
+        {
             let texture = device.create_texture(&TextureDescriptor {
                 label: Some("webcam_img"),
                 size,
@@ -252,110 +491,220 @@ impl Node for BackgroundNode {
                 usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
                 view_formats: &[],
             });
-
-            queue.write_texture(
-                ImageCopyTexture {
-                    aspect: TextureAspect::All,
-                    texture: &texture,
-                    mip_level: 0,
-                    origin: Origin3d::ZERO,
-                },
-                &img.0,
-                ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(4 * dimensions.0),
-                    rows_per_image: Some(dimensions.1),
+            let view = texture.create_view(&TextureViewDescriptor::default());
+            let bind_group = device.create_bind_group(
+                Some("diffuse_bind_group"),
+                &pipeline.texture_bind_group_layout,
+                &BindGroupEntries::sequential((&view, &pipeline.sampler)),
+            );
+            textures.insert(
+                *entity,
+                BackgroundTexture {
+                    texture,
+                    bind_group,
+                    image: Weak::new(),
                 },
-                size,
             );
+        }
 
-            let view = texture.create_view(&TextureViewDescriptor::default());
-            let sampler = device.create_sampler(&SamplerDescriptor {
-                address_mode_u: AddressMode::ClampToEdge,
-                address_mode_v: AddressMode::ClampToEdge,
-                address_mode_w: AddressMode::ClampToEdge,
-                mag_filter: FilterMode::Linear,
-                min_filter: FilterMode::Nearest,
-                mipmap_filter: FilterMode::Nearest,
-                ..Default::default()
-            });
+        let texture = textures.get_mut(entity).unwrap();
+        texture.image = Arc::downgrade(&frame.image);
+        queue.write_texture(
+            ImageCopyTexture {
+                aspect: TextureAspect::All,
+                texture: &texture.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+            },
+            frame.image.as_raw(),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * dimensions.0),
+                rows_per_image: Some(dimensions.1),
+            },
+            size,
+        );
+    }
+}
 
-            let texture_bind_group_layout = device.create_bind_group_layout(
-                "texture_bind_group_layout",
-                &[
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: TextureViewDimension::D2,
-                            sample_type: TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            );
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn prepare_view_backgrounds(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    pipeline_cache: Res<PipelineCache>,
+    pipeline: Res<BackgroundPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<BackgroundPipeline>>,
+    images: Res<ExtractedBackgroundImages>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &ViewTarget,
+        &Msaa,
+        &MainEntity,
+        Option<&ShowCameraBackground>,
+        Option<&BackgroundFit>,
+    )>,
+    mut backgrounds: ResMut<ViewBackgrounds>,
+) {
+    backgrounds.retain(|entity, _| views.contains(*entity));
+
+    for (entity, view, target, msaa, main_entity, show, fit) in views.iter() {
+        // an explicit choice, then the camera on the view itself
+        let camera = show.map_or(main_entity.id(), |show| show.0);
+        let frame = images.get_key_value(&camera);
+        let Some((camera, ExtractedBackgroundImage { frame, orientation })) = frame else {
+            backgrounds.remove(&entity);
+            continue;
+        };
 
-            let diffuse_bind_group = device.create_bind_group(
-                Some("diffuse_bind_group"),
-                &texture_bind_group_layout,
-                &BindGroupEntries::sequential((&view, &sampler)),
-            );
+        let fit = fit.copied().unwrap_or_default();
+        let (width, height) = frame.image.dimensions();
+        let size = orientation.oriented_size(width, height);
+        let (position, uv) = fit.layout(size.into(), view.viewport.zw());
+        let letterbox = fit.letterbox();
+        let full = Rect::new(-1.0, -1.0, 1.0, 1.0);
+        let vertices = [
+            quad(
+                full,
+                full,
+                CameraOrientation::default(),
+                letterbox.unwrap_or(LinearRgba::NONE),
+            ),
+            quad(position, uv, *orientation, LinearRgba::NONE),
+        ];
+        let contents = bytemuck::cast_slice(&vertices);
+
+        let key = BackgroundPipelineKey {
+            samples: msaa.samples(),
+            hdr: view.hdr,
+            format: target.main_texture_format(),
+        };
+        let render_pipeline = pipelines.specialize(&pipeline_cache, &pipeline, key);
+
+        match backgrounds.get_mut(&entity) {
+            Some(background) => {
+                queue.write_buffer(&background.vertex_buffer, 0, contents);
+                background.camera = *camera;
+                background.pipeline = render_pipeline;
+                background.letterbox = letterbox.is_some();
+            }
+            None => {
+                let vertex_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("Vertex Buffer"),
+                    contents,
+                    usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                });
+                backgrounds.insert(
+                    entity,
+                    ViewBackground {
+                        camera: *camera,
+                        pipeline: render_pipeline,
+                        vertex_buffer,
+                        letterbox: letterbox.is_some(),
+                    },
+                );
+            }
+        }
+    }
+}
 
-            self.diffuse_bind_group = Some(diffuse_bind_group);
+pub fn handle_background_image(mut cam_query: Query<(&BackgroundCamera, &mut BackgroundImage)>) {
+    for (background_camera, mut image) in cam_query.iter_mut() {
+        let frames = background_camera.image_rx.drain();
+        let skipped = frames.len().saturating_sub(1) as u64;
+        if let Some(frame) = frames.last() {
+            background_camera.frame_counters.consumed();
+            background_camera.frame_counters.dropped(skipped);
+            image.0 = Some(frame);
         }
     }
+}
 
-    fn run(
-        &self,
-        _graph: &mut RenderGraphContext,
-        render_context: &mut RenderContext,
-        world: &World,
-    ) -> Result<(), NodeRunError> {
-        for target in self.query.iter_manual(world) {
-            let pipeline = world.get_resource::<BackgroundPipeline>().unwrap();
-            let pass_descriptor = RenderPassDescriptor {
-                label: Some("background_pass"),
-                color_attachments: &[Some(target.get_color_attachment())],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOUR_THREE: UVec2 = UVec2::new(640, 480);
+    const SIXTEEN_NINE: UVec2 = UVec2::new(1920, 1080);
+    const WHOLE_IMAGE: Rect = Rect {
+        min: Vec2::ZERO,
+        max: Vec2::ONE,
+    };
+
+    fn assert_rect(actual: Rect, expected: Rect) {
+        assert!(
+            actual.min.abs_diff_eq(expected.min, 1e-6)
+                && actual.max.abs_diff_eq(expected.max, 1e-6),
+            "{actual:?} != {expected:?}"
+        );
+    }
 
-            if let (Some(vertex_buffer), Some(index_buffer)) =
-                (&self.vertex_buffer, &self.index_buffer)
-            {
-                let mut render_pass = render_context
-                    .command_encoder()
-                    .begin_render_pass(&pass_descriptor);
+    #[test]
+    fn stretch_fills_the_viewport() {
+        let (position, uv) = BackgroundFit::Stretch.layout(FOUR_THREE, SIXTEEN_NINE);
+        assert_rect(position, Rect::new(-1.0, -1.0, 1.0, 1.0));
+        assert_rect(uv, WHOLE_IMAGE);
+    }
 
-                render_pass.set_pipeline(&pipeline.render_pipeline);
+    #[test]
+    fn contain_letterboxes_the_sides() {
+        let fit = BackgroundFit::Contain { letterbox: None };
+        let (position, uv) = fit.layout(FOUR_THREE, SIXTEEN_NINE);
+        assert_rect(position, Rect::new(-0.75, -1.0, 0.75, 1.0));
+        assert_rect(uv, WHOLE_IMAGE);
 
-                render_pass.set_bind_group(0, self.diffuse_bind_group.as_ref().unwrap(), &[]);
-                render_pass.set_vertex_buffer(0, *vertex_buffer.slice(..));
-                render_pass.set_index_buffer(*index_buffer.slice(..), IndexFormat::Uint16);
+        // a wide image in a square viewport gets bars above and below instead
+        let (position, _) = fit.layout(SIXTEEN_NINE, UVec2::splat(1000));
+        assert_rect(position, Rect::new(-1.0, -0.5625, 1.0, 0.5625));
+    }
 
-                render_pass.draw_indexed(0..(INDICES.len() as u32), 0, 0..1);
-            }
-        }
+    #[test]
+    fn cover_crops_the_top_and_bottom() {
+        let (position, uv) = BackgroundFit::Cover.layout(FOUR_THREE, SIXTEEN_NINE);
+        assert_rect(position, Rect::new(-1.0, -1.0, 1.0, 1.0));
+        assert_rect(uv, Rect::new(0.0, 0.125, 1.0, 0.875));
+        assert!((uv.height() - 0.75).abs() < 1e-6);
+    }
 
-        Ok(())
+    #[test]
+    fn actual_size_maps_pixels_one_to_one() {
+        let (position, uv) = BackgroundFit::ActualSize.layout(FOUR_THREE, UVec2::new(1280, 960));
+        assert_rect(position, Rect::new(-0.5, -0.5, 0.5, 0.5));
+        assert_rect(uv, WHOLE_IMAGE);
+
+        // larger than the viewport runs off its edges
+        let (position, _) = BackgroundFit::ActualSize.layout(FOUR_THREE, UVec2::new(320, 240));
+        assert_rect(position, Rect::new(-2.0, -2.0, 2.0, 2.0));
     }
-}
 
-pub fn handle_background_image(
-    cam_query: Query<&mut BackgroundCamera>,
-    mut image: ResMut<BackgroundImage>,
-) {
-    for background_camera in cam_query.iter() {
-        while let Some(img) = background_camera.image_rx.drain().last() {
-            image.0 = img;
+    #[test]
+    fn rect_is_measured_from_the_top_left() {
+        let fit = BackgroundFit::Rect(Rect::new(0.25, 0.0, 0.75, 0.5));
+        let (position, uv) = fit.layout(FOUR_THREE, SIXTEEN_NINE);
+        assert_rect(position, Rect::new(-0.5, 0.0, 0.5, 1.0));
+        assert_rect(uv, WHOLE_IMAGE);
+    }
+
+    #[test]
+    fn empty_sizes_stay_finite() {
+        for fit in [
+            BackgroundFit::Contain { letterbox: None },
+            BackgroundFit::Cover,
+            BackgroundFit::ActualSize,
+        ] {
+            let (position, uv) = fit.layout(UVec2::ZERO, UVec2::ZERO);
+            assert!(position.min.is_finite() && position.max.is_finite());
+            assert!(uv.min.is_finite() && uv.max.is_finite());
         }
     }
+
+    #[test]
+    fn only_contain_has_an_opaque_letterbox() {
+        let fit = BackgroundFit::Contain {
+            letterbox: Some(Color::srgba(1.0, 0.0, 0.0, 0.5)),
+        };
+        assert_eq!(fit.letterbox().map(|color| color.alpha), Some(1.0));
+        assert_eq!(BackgroundFit::Contain { letterbox: None }.letterbox(), None);
+        assert_eq!(BackgroundFit::Cover.letterbox(), None);
+    }
 }
@@ -1,22 +1,43 @@
-use anyhow::Result;
-use bevy::prelude::Component;
-use flume::{bounded, unbounded};
-use image::RgbaImage;
-use nokhwa::pixel_format::{RgbAFormat, RgbFormat};
+use crate::background::BackgroundImage;
+use crate::control::{CameraControlExt, ControlValue};
+use crate::discovery::CameraDevice;
+use crate::error::{BevyNokhwaError, Result};
+use crate::frame::{CameraFrame, CameraOrientation, FrameCounters, FrameQueue, FrameStats};
+use crate::selector::CameraSelector;
+use crate::source::{FrameSource, NokhwaSource, TestPattern, TestPatternSource};
+use crate::worker::{Worker, WorkerEvent};
+use bevy::log::{debug, warn};
+use bevy::prelude::{AppExit, Component, EventReader, Query};
+use flume::{unbounded, RecvTimeoutError};
 use nokhwa::utils::{
-    ApiBackend, CameraControl, CameraIndex, ControlValueSetter, KnownCameraControl,
-    RequestedFormat, RequestedFormatType,
+    ApiBackend, CameraControl, CameraFormat, CameraIndex, ControlValueSetter, KnownCameraControl,
+    RequestedFormatType, Resolution,
 };
-use nokhwa::CallbackCamera;
-use nokhwa::{nokhwa_initialize, query};
 use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How long [`BackgroundCamera::stop`] waits for the device to be released.
+pub const STOP_TIMEOUT: Duration = Duration::from_millis(500);
 
 #[derive(Component)]
+#[require(CameraStatus, NegotiatedFormat, CameraOrientation, BackgroundImage)]
 pub struct BackgroundCamera {
-    pub image_rx: flume::Receiver<RgbaImage>,
+    pub image_rx: flume::Receiver<CameraFrame>,
     pub operation_tx: flume::Sender<CameraOperation>,
     pub known_controls: BTreeMap<KnownCameraControl, CameraControl>,
     pub controls: BTreeMap<KnownCameraControl, ControlValueSetter>,
+    pub(crate) event_rx: flume::Receiver<WorkerEvent>,
+    pub(crate) frame_counters: Arc<FrameCounters>,
+    frame_queue: FrameQueue,
+    /// Control values sent to the worker that it has not reported back on yet.
+    pending_controls: BTreeMap<KnownCameraControl, ControlValueSetter>,
+    device: Option<CameraDevice>,
+    reconnect: Option<ReconnectPolicy>,
+    worker: Option<JoinHandle<()>>,
+    /// Disconnects once the worker thread exited.
+    worker_done: flume::Receiver<()>,
 }
 
 pub enum CameraOperation {
@@ -24,6 +45,82 @@ pub enum CameraOperation {
         id: KnownCameraControl,
         control: ControlValueSetter,
     },
+    /// Stop the stream, keeping the camera around so it can be resumed.
+    Pause,
+    Resume,
+    SetReconnect(Option<ReconnectPolicy>),
+    SetFrameQueue(FrameQueue),
+    /// Stop the stream, renegotiate the format and restart it.
+    ///
+    /// The result is reported in [`NegotiatedFormat`] and a
+    /// [`CameraFormatChanged`](crate::event::CameraFormatChanged) event, the camera keeps its old
+    /// format if the device rejects the request.
+    SetFormat(RequestedFormatType),
+    /// Stop the worker for good and release the device, see [`BackgroundCamera::stop`].
+    Stop,
+}
+
+/// How to find a device again after it was unplugged, indices may change in between.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DeviceMatch {
+    #[default]
+    Name,
+    /// The backend specific unique id, see [`CameraInfo::misc`](nokhwa::utils::CameraInfo::misc).
+    UniqueId,
+}
+
+/// Retry opening a camera that stopped delivering frames.
+///
+/// The delay before attempt `n` is `interval * backoff^n`, capped at `max_interval`. The stream
+/// reopens with the format it had and the last control values sent to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub interval: Duration,
+    pub backoff: f32,
+    pub max_interval: Duration,
+    /// Give up after this many failed attempts, `None` retries forever.
+    pub max_attempts: Option<u32>,
+    pub match_by: DeviceMatch,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            backoff: 2.0,
+            max_interval: Duration::from_secs(30),
+            max_attempts: None,
+            match_by: DeviceMatch::Name,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .backoff
+            .max(1.0)
+            .powi(attempt.min(i32::MAX as u32) as i32);
+        Duration::try_from_secs_f32(self.interval.as_secs_f32() * factor)
+            .unwrap_or(self.max_interval)
+            .min(self.max_interval)
+    }
+}
+
+/// The format a [`BackgroundCamera`] captures in, `None` until the device reported it.
+#[derive(Component, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct NegotiatedFormat(pub Option<CameraFormat>);
+
+/// Where a [`BackgroundCamera`] is in its lifecycle, kept up to date by the plugin.
+#[derive(Component, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum CameraStatus {
+    /// Opened, but no frame has arrived yet.
+    #[default]
+    Initializing,
+    Streaming,
+    Paused,
+    /// The worker stopped, see the preceding [`CameraError`](crate::event::CameraError) event.
+    Failed,
 }
 
 impl BackgroundCamera {
@@ -36,55 +133,74 @@ impl BackgroundCamera {
         index: Option<CameraIndex>,
         request_format_type: Option<RequestedFormatType>,
     ) -> Result<Self> {
-        nokhwa_initialize(|granted| {
-            println!("User said {granted}");
-        });
-        let cameras = query(api)?;
-        cameras.iter().for_each(|cam| println!("{cam:?}",));
-        let (sender, receiver) = unbounded();
-        let (op_tx, op_rx) = bounded(1);
-        let first_camera = cameras.first().expect("camera not exist");
+        Self::from_source(NokhwaSource::new(api, index, request_format_type)?)
+    }
 
-        let format = RequestedFormat::new::<RgbFormat>(
-            request_format_type.unwrap_or(RequestedFormatType::AbsoluteHighestFrameRate),
-        );
+    /// Open the first device matched by `selectors`, trying them in order.
+    ///
+    /// Returns the error of the last selector tried if none of them worked.
+    pub fn select(
+        api: ApiBackend,
+        selectors: impl IntoIterator<Item = CameraSelector>,
+        request_format_type: Option<RequestedFormatType>,
+    ) -> Result<Self> {
+        let mut last_error = BevyNokhwaError::NoDevices;
+        for selector in selectors {
+            let camera = match selector {
+                CameraSelector::TestPattern {
+                    pattern,
+                    resolution,
+                    frame_rate,
+                } => Ok(Self::test_pattern(pattern, resolution, frame_rate)),
+                selector => NokhwaSource::select(api, &selector, request_format_type)
+                    .and_then(Self::from_source),
+            };
+            match camera {
+                Ok(camera) => return Ok(camera),
+                Err(err) => {
+                    debug!("no camera {err}");
+                    last_error = err;
+                }
+            }
+        }
 
-        let first_camera_index: CameraIndex = match index {
-            None => first_camera.index().clone(),
-            Some(index) => index,
-        };
+        Err(last_error)
+    }
 
-        let callback_fn = move |buffer: nokhwa::Buffer| {
-            let image = buffer.decode_image::<RgbAFormat>().unwrap();
-            let _ = sender.send(image);
-        };
+    /// A synthetic camera that does not need any device attached.
+    pub fn test_pattern(pattern: TestPattern, resolution: Resolution, frame_rate: u32) -> Self {
+        Self::from_source(TestPatternSource::new(pattern, resolution, frame_rate))
+            .expect("test pattern source never fails to open")
+    }
 
-        let mut threaded = CallbackCamera::new(first_camera_index, format, callback_fn).unwrap();
-        let known_controls = threaded.camera_controls_known_camera_controls().unwrap();
-        println!("support controls: {known_controls:#?}");
-
-        threaded.open_stream().unwrap();
-
-        std::thread::spawn(move || {
-            #[allow(clippy::empty_loop)]
-            loop {
-                if let Ok(op) = op_rx.try_recv() {
-                    match op {
-                        CameraOperation::Control { id, control } => {
-                            println!("set control: {id} {control}");
-                            threaded
-                                .set_camera_control(id, control)
-                                .expect("camera error");
-                        }
-                    };
-                };
-
-                threaded.last_frame().expect("camera error");
-            }
-        });
+    /// Drive the camera from any [`FrameSource`] instead of a physical device.
+    pub fn from_source(mut source: impl FrameSource) -> Result<Self> {
+        // bounded by the worker instead of the channel, so the depth can change at runtime
+        let (sender, receiver) = unbounded();
+        // control updates are coalesced by the worker, so nothing is lost or piles up
+        let (op_tx, op_rx) = unbounded();
+        let (event_tx, event_rx) = unbounded();
 
-        let known_controls: BTreeMap<KnownCameraControl, CameraControl> =
-            known_controls.into_iter().collect();
+        let device = source.device();
+        let known_controls = source.controls()?;
+        debug!("supported controls: {known_controls:?}");
+
+        source.open()?;
+
+        let _ = event_tx.send(WorkerEvent::Opened);
+        if let Some(format) = source.format() {
+            let _ = event_tx.send(WorkerEvent::FormatChanged(format));
+        }
+
+        let frame_counters = Arc::new(FrameCounters::default());
+        let (worker, worker_done) = Worker::new(
+            source,
+            (sender, receiver.clone()),
+            op_rx,
+            event_tx,
+            frame_counters.clone(),
+        )
+        .spawn();
 
         let controls = known_controls
             .iter()
@@ -99,9 +215,168 @@ impl BackgroundCamera {
             operation_tx: op_tx,
             known_controls,
             controls,
+            event_rx,
+            frame_counters,
+            frame_queue: FrameQueue::default(),
+            pending_controls: BTreeMap::new(),
+            device,
+            reconnect: None,
+            worker: Some(worker),
+            worker_done,
         })
     }
 
+    /// Stop streaming and wait for the device to be released, so another camera can open it.
+    ///
+    /// Happens on its own when the component is dropped or the app exits. The camera delivers no
+    /// frames afterwards. A device stuck waiting for a frame is left to release itself in the
+    /// background after [`STOP_TIMEOUT`], instead of blocking the app.
+    pub fn stop(&mut self) {
+        let Some(worker) = self.worker.take() else {
+            return;
+        };
+        // fails if the worker already stopped by itself
+        let _ = self.operation_tx.send(CameraOperation::Stop);
+
+        if let Err(RecvTimeoutError::Timeout) = self.worker_done.recv_timeout(STOP_TIMEOUT) {
+            warn!("camera did not stop within {STOP_TIMEOUT:?}, releasing it in the background");
+            return;
+        }
+        let _ = worker.join();
+    }
+
+    /// Name, description and supported formats of the device, if the source has one.
+    ///
+    /// The format currently in use is in the [`NegotiatedFormat`] component next to this one. Both
+    /// are updated after reconnecting, which may have found another unit with the same name.
+    pub fn device(&self) -> Option<&CameraDevice> {
+        self.device.as_ref()
+    }
+
+    /// Switch resolution, frame rate or frame format while streaming.
+    pub fn set_format(&self, request: RequestedFormatType) {
+        let _ = self.operation_tx.send(CameraOperation::SetFormat(request));
+    }
+
+    pub fn with_frame_queue(mut self, queue: FrameQueue) -> Self {
+        self.set_frame_queue(queue);
+        self
+    }
+
+    pub fn set_frame_queue(&mut self, queue: FrameQueue) {
+        self.frame_queue = queue;
+        let _ = self
+            .operation_tx
+            .send(CameraOperation::SetFrameQueue(queue));
+    }
+
+    pub fn frame_queue(&self) -> FrameQueue {
+        self.frame_queue
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_counters.stats()
+    }
+
+    /// Opt in to reopening the camera when it goes away.
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.set_reconnect(Some(policy));
+        self
+    }
+
+    pub fn set_reconnect(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect.clone_from(&policy);
+        let _ = self
+            .operation_tx
+            .send(CameraOperation::SetReconnect(policy));
+    }
+
+    pub fn reconnect(&self) -> Option<&ReconnectPolicy> {
+        self.reconnect.as_ref()
+    }
+
+    /// Validate a control value and send it to the device.
+    ///
+    /// The value is clamped and snapped to what the control accepts, see
+    /// [`CameraControlExt::validate`], and returned as sent. Values sent faster than the device
+    /// takes them are coalesced, the last one always gets through. The outcome is reported as a
+    /// [`CameraControlResult`](crate::event::CameraControlResult) and
+    /// [`BackgroundCamera::controls`] is updated with the value the device reports afterwards.
+    pub fn set_control(
+        &mut self,
+        id: KnownCameraControl,
+        value: impl ControlValue,
+    ) -> Result<ControlValueSetter> {
+        let value = self
+            .known_controls
+            .get(&id)
+            .ok_or_else(|| BevyNokhwaError::InvalidControl {
+                id,
+                reason: "not supported by this camera".to_string(),
+            })?
+            .validate(value.into_setter())?;
+
+        self.controls.insert(id, value.clone());
+        self.pending_controls.insert(id, value.clone());
+        let _ = self.operation_tx.send(CameraOperation::Control {
+            id,
+            control: value.clone(),
+        });
+        Ok(value)
+    }
+
+    /// Set a control back to its default value.
+    pub fn reset_control(&mut self, id: KnownCameraControl) -> Result<ControlValueSetter> {
+        let default = self
+            .known_controls
+            .get(&id)
+            .map(CameraControlExt::default_value)
+            .unwrap_or(ControlValueSetter::None);
+        self.set_control(id, default)
+    }
+
+    /// The last value set or read back for a control, `None` if it is not of type `T`.
+    pub fn control_value<T: ControlValue>(&self, id: KnownCameraControl) -> Option<T> {
+        T::from_setter(self.controls.get(&id)?)
+    }
+
+    /// What a control accepts, see [`CameraControlExt`] for its range, step and options.
+    pub fn control(&self, id: KnownCameraControl) -> Option<&CameraControl> {
+        self.known_controls.get(&id)
+    }
+
+    /// Take in the device after it was reopened or switched format.
+    ///
+    /// The formats listed before are kept if the device could not list them while streaming.
+    pub(crate) fn device_changed(&mut self, mut device: CameraDevice) {
+        if let Some(old) = self.device.take() {
+            if device.formats.is_empty() && old.info == device.info {
+                device.formats = old.formats;
+            }
+        }
+        self.device = Some(device);
+    }
+
+    /// Take in what the device reports after setting `value`, unless a newer value is on its way.
+    pub(crate) fn control_updated(
+        &mut self,
+        id: KnownCameraControl,
+        value: &ControlValueSetter,
+        current: Option<CameraControl>,
+    ) {
+        match self.pending_controls.get(&id) {
+            Some(pending) if pending != value => return,
+            Some(_) => {
+                self.pending_controls.remove(&id);
+            }
+            None => {}
+        }
+        if let Some(current) = current {
+            self.controls.insert(id, current.value());
+            self.known_controls.insert(id, current);
+        }
+    }
+
     pub fn get_mut_bool_control(&mut self, id: &KnownCameraControl) -> Option<&mut bool> {
         if let Some(ControlValueSetter::Boolean(value)) = self.controls.get_mut(id) {
             Some(value)
@@ -126,3 +401,95 @@ impl BackgroundCamera {
         }
     }
 }
+
+impl Drop for BackgroundCamera {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+pub(crate) fn stop_cameras_on_exit(
+    mut exit: EventReader<AppExit>,
+    mut cam_query: Query<&mut BackgroundCamera>,
+) {
+    if exit.read().last().is_some() {
+        for mut camera in cam_query.iter_mut() {
+            camera.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+    use std::time::Instant;
+
+    /// Never delivers a frame, like a device that stalls without reporting an error.
+    struct StalledSource;
+
+    impl FrameSource for StalledSource {
+        fn open(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn next_frame(&mut self, _image: &mut RgbaImage) -> Result<()> {
+            std::thread::sleep(Duration::from_secs(5));
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stop_does_not_wait_for_a_stalled_device() {
+        let camera = BackgroundCamera::from_source(StalledSource).unwrap();
+        // let the worker get stuck in next_frame
+        std::thread::sleep(Duration::from_millis(50));
+
+        let started = Instant::now();
+        drop(camera);
+        assert!(started.elapsed() < STOP_TIMEOUT + Duration::from_millis(500));
+    }
+
+    #[test]
+    fn stop_joins_a_responsive_worker() {
+        let mut camera =
+            BackgroundCamera::test_pattern(TestPattern::ColorBars, Resolution::new(8, 8), 100);
+        std::thread::sleep(Duration::from_millis(50));
+
+        let started = Instant::now();
+        camera.stop();
+        assert!(started.elapsed() < STOP_TIMEOUT);
+        assert!(camera.worker.is_none());
+    }
+
+    #[test]
+    fn select_falls_back_to_the_test_pattern() {
+        let mut camera = BackgroundCamera::select(
+            ApiBackend::Auto,
+            [
+                CameraSelector::Name("no camera is called like this".to_string()),
+                CameraSelector::TestPattern {
+                    pattern: TestPattern::Checkerboard,
+                    resolution: Resolution::new(8, 8),
+                    frame_rate: 100,
+                },
+            ],
+            None,
+        )
+        .unwrap();
+
+        let device = camera.device().expect("the test pattern describes itself");
+        assert_eq!(device.info.human_name(), "Test Pattern");
+        assert_eq!(device.info.description(), "Checkerboard");
+        let frame = camera
+            .image_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the test pattern streams");
+        assert_eq!(frame.image.dimensions(), (8, 8));
+        camera.stop();
+    }
+}
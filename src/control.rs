@@ -0,0 +1,506 @@
+use crate::error::{BevyNokhwaError, Result};
+use nokhwa::utils::{
+    CameraControl, ControlValueDescription, ControlValueSetter, KnownCameraControl,
+    KnownCameraControlFlag,
+};
+use std::ops::RangeInclusive;
+
+/// A Rust type a camera control value can be read as and written from.
+///
+/// Enum controls read and write as `i64`, RGB controls as `(f64, f64, f64)`.
+pub trait ControlValue: Sized {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self>;
+
+    fn into_setter(self) -> ControlValueSetter;
+}
+
+impl ControlValue for ControlValueSetter {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self> {
+        Some(setter.clone())
+    }
+
+    fn into_setter(self) -> ControlValueSetter {
+        self
+    }
+}
+
+impl ControlValue for bool {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self> {
+        setter.as_boolean().copied()
+    }
+
+    fn into_setter(self) -> ControlValueSetter {
+        ControlValueSetter::Boolean(self)
+    }
+}
+
+impl ControlValue for i64 {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self> {
+        setter.as_integer().or(setter.as_enum()).copied()
+    }
+
+    fn into_setter(self) -> ControlValueSetter {
+        ControlValueSetter::Integer(self)
+    }
+}
+
+impl ControlValue for f64 {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self> {
+        setter.as_float().copied()
+    }
+
+    fn into_setter(self) -> ControlValueSetter {
+        ControlValueSetter::Float(self)
+    }
+}
+
+impl ControlValue for String {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self> {
+        setter.as_str().map(str::to_string)
+    }
+
+    fn into_setter(self) -> ControlValueSetter {
+        ControlValueSetter::String(self)
+    }
+}
+
+impl ControlValue for Vec<u8> {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self> {
+        setter.as_bytes().map(<[u8]>::to_vec)
+    }
+
+    fn into_setter(self) -> ControlValueSetter {
+        ControlValueSetter::Bytes(self)
+    }
+}
+
+impl ControlValue for (i128, i128) {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self> {
+        setter.as_key_value().map(|(key, value)| (*key, *value))
+    }
+
+    fn into_setter(self) -> ControlValueSetter {
+        ControlValueSetter::KeyValue(self.0, self.1)
+    }
+}
+
+impl ControlValue for (f64, f64) {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self> {
+        setter.as_point().map(|(x, y)| (*x, *y))
+    }
+
+    fn into_setter(self) -> ControlValueSetter {
+        ControlValueSetter::Point(self.0, self.1)
+    }
+}
+
+impl ControlValue for (f64, f64, f64) {
+    fn from_setter(setter: &ControlValueSetter) -> Option<Self> {
+        setter.as_rgb().map(|(r, g, b)| (*r, *g, *b))
+    }
+
+    fn into_setter(self) -> ControlValueSetter {
+        ControlValueSetter::RGB(self.0, self.1, self.2)
+    }
+}
+
+/// Typed access to what a [`CameraControl`] accepts, as described by its
+/// [`ControlValueDescription`].
+pub trait CameraControlExt {
+    /// The value the control resets to.
+    fn default_value(&self) -> ControlValueSetter;
+
+    /// Smallest and largest value of a ranged control, RGB channels range from 0 to their maximum.
+    fn range<T: ControlValue>(&self) -> Option<RangeInclusive<T>>;
+
+    /// Distance between two valid values of a numeric control, `None` if any value goes.
+    fn step<T: ControlValue>(&self) -> Option<T>;
+
+    /// Values a menu control can take.
+    fn options(&self) -> Option<&[i64]>;
+
+    fn is_read_only(&self) -> bool;
+
+    /// Whether the device currently adjusts the control by itself.
+    fn is_automatic(&self) -> bool;
+
+    /// Check `value` against what the control accepts.
+    ///
+    /// Numbers are clamped to their range and snapped to their step, an `i64` sent to an enum
+    /// control becomes an enum value. Returns the value to send to the device.
+    fn validate(&self, value: ControlValueSetter) -> Result<ControlValueSetter>;
+}
+
+impl CameraControlExt for CameraControl {
+    fn default_value(&self) -> ControlValueSetter {
+        match self.description() {
+            ControlValueDescription::None => ControlValueSetter::None,
+            ControlValueDescription::Integer { default, .. }
+            | ControlValueDescription::IntegerRange { default, .. } => {
+                ControlValueSetter::Integer(*default)
+            }
+            ControlValueDescription::Float { default, .. }
+            | ControlValueDescription::FloatRange { default, .. } => {
+                ControlValueSetter::Float(*default)
+            }
+            ControlValueDescription::Boolean { default, .. } => {
+                ControlValueSetter::Boolean(*default)
+            }
+            ControlValueDescription::String { default, value } => {
+                ControlValueSetter::String(default.clone().unwrap_or_else(|| value.clone()))
+            }
+            ControlValueDescription::Bytes { default, .. } => {
+                ControlValueSetter::Bytes(default.clone())
+            }
+            ControlValueDescription::KeyValuePair { default, .. } => {
+                ControlValueSetter::KeyValue(default.0, default.1)
+            }
+            ControlValueDescription::Point { default, .. } => {
+                ControlValueSetter::Point(default.0, default.1)
+            }
+            ControlValueDescription::Enum { default, .. } => {
+                ControlValueSetter::EnumValue(*default)
+            }
+            ControlValueDescription::RGB { default, .. } => {
+                ControlValueSetter::RGB(default.0, default.1, default.2)
+            }
+        }
+    }
+
+    fn range<T: ControlValue>(&self) -> Option<RangeInclusive<T>> {
+        let (min, max) = match self.description() {
+            ControlValueDescription::IntegerRange { min, max, .. } => (
+                ControlValueSetter::Integer(*min),
+                ControlValueSetter::Integer(*max),
+            ),
+            ControlValueDescription::FloatRange { min, max, .. } => (
+                ControlValueSetter::Float(*min),
+                ControlValueSetter::Float(*max),
+            ),
+            ControlValueDescription::RGB { max, .. } => (
+                ControlValueSetter::RGB(0.0, 0.0, 0.0),
+                ControlValueSetter::RGB(max.0, max.1, max.2),
+            ),
+            _ => return None,
+        };
+        Some(T::from_setter(&min)?..=T::from_setter(&max)?)
+    }
+
+    fn step<T: ControlValue>(&self) -> Option<T> {
+        let step = match self.description() {
+            ControlValueDescription::Integer { step, .. }
+            | ControlValueDescription::IntegerRange { step, .. }
+                if *step > 0 =>
+            {
+                ControlValueSetter::Integer(*step)
+            }
+            ControlValueDescription::Float { step, .. }
+            | ControlValueDescription::FloatRange { step, .. }
+                if *step > 0.0 =>
+            {
+                ControlValueSetter::Float(*step)
+            }
+            _ => return None,
+        };
+        T::from_setter(&step)
+    }
+
+    fn options(&self) -> Option<&[i64]> {
+        match self.description() {
+            ControlValueDescription::Enum { possible, .. } => Some(possible),
+            _ => None,
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.flag().iter().any(|flag| {
+            matches!(
+                flag,
+                KnownCameraControlFlag::ReadOnly | KnownCameraControlFlag::Disabled
+            )
+        })
+    }
+
+    fn is_automatic(&self) -> bool {
+        self.flag().contains(&KnownCameraControlFlag::Automatic)
+    }
+
+    fn validate(&self, value: ControlValueSetter) -> Result<ControlValueSetter> {
+        let id = self.control();
+        if self.is_read_only() {
+            return Err(invalid(id, "the control is read only"));
+        }
+
+        let value = match (self.description(), value) {
+            (ControlValueDescription::None, ControlValueSetter::None) => ControlValueSetter::None,
+            (
+                ControlValueDescription::Integer { default, step, .. },
+                ControlValueSetter::Integer(value),
+            ) => ControlValueSetter::Integer(snap_i64(value, *default, *step)),
+            (
+                ControlValueDescription::IntegerRange { min, max, step, .. },
+                ControlValueSetter::Integer(value),
+            ) => {
+                let value = snap_i64(value.clamp(*min, (*max).max(*min)), *min, *step);
+                // snapping may round past the end of the range, a range ending before it starts
+                // only holds `min`
+                let back = value.saturating_sub(*step);
+                ControlValueSetter::Integer(if value > *max && back >= *min {
+                    back
+                } else {
+                    value
+                })
+            }
+            (_, ControlValueSetter::Float(value)) if !value.is_finite() => {
+                return Err(invalid(id, "not a finite number"));
+            }
+            (
+                ControlValueDescription::Float { default, step, .. },
+                ControlValueSetter::Float(value),
+            ) => ControlValueSetter::Float(snap_f64(value, *default, *step)),
+            (
+                ControlValueDescription::FloatRange { min, max, step, .. },
+                ControlValueSetter::Float(value),
+            ) => {
+                let value = snap_f64(value.clamp(*min, max.max(*min)), *min, *step);
+                let back = value - step;
+                ControlValueSetter::Float(if value > *max && back >= *min {
+                    back
+                } else {
+                    value
+                })
+            }
+            (ControlValueDescription::Boolean { .. }, value @ ControlValueSetter::Boolean(_))
+            | (ControlValueDescription::String { .. }, value @ ControlValueSetter::String(_))
+            | (ControlValueDescription::Bytes { .. }, value @ ControlValueSetter::Bytes(_))
+            | (
+                ControlValueDescription::KeyValuePair { .. },
+                value @ ControlValueSetter::KeyValue(..),
+            ) => value,
+            (ControlValueDescription::Point { .. }, ControlValueSetter::Point(x, y)) => {
+                if !x.is_finite() || !y.is_finite() {
+                    return Err(invalid(id, "not a finite point"));
+                }
+                ControlValueSetter::Point(x, y)
+            }
+            (
+                ControlValueDescription::Enum { possible, .. },
+                ControlValueSetter::EnumValue(value) | ControlValueSetter::Integer(value),
+            ) => {
+                if !possible.is_empty() && !possible.contains(&value) {
+                    return Err(invalid(id, &format!("{value} is not one of {possible:?}")));
+                }
+                ControlValueSetter::EnumValue(value)
+            }
+            (ControlValueDescription::RGB { max, .. }, ControlValueSetter::RGB(r, g, b)) => {
+                if !r.is_finite() || !g.is_finite() || !b.is_finite() {
+                    return Err(invalid(id, "not a finite color"));
+                }
+                ControlValueSetter::RGB(
+                    r.clamp(0.0, max.0.max(0.0)),
+                    g.clamp(0.0, max.1.max(0.0)),
+                    b.clamp(0.0, max.2.max(0.0)),
+                )
+            }
+            (description, value) => {
+                return Err(invalid(id, &format!("{value} does not fit {description}")));
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+fn snap_i64(value: i64, origin: i64, step: i64) -> i64 {
+    if step <= 0 {
+        return value;
+    }
+    let offset = value.saturating_sub(origin);
+    // round half away from the origin
+    let steps = offset.saturating_add(offset.signum() * (step / 2)) / step;
+    origin.saturating_add(steps.saturating_mul(step))
+}
+
+fn snap_f64(value: f64, origin: f64, step: f64) -> f64 {
+    if step <= 0.0 || !step.is_finite() {
+        return value;
+    }
+    origin + ((value - origin) / step).round() * step
+}
+
+fn invalid(id: KnownCameraControl, reason: &str) -> BevyNokhwaError {
+    BevyNokhwaError::InvalidControl {
+        id,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(description: ControlValueDescription) -> CameraControl {
+        CameraControl::new(
+            KnownCameraControl::Brightness,
+            "Brightness".to_string(),
+            description,
+            vec![],
+            true,
+        )
+    }
+
+    fn integer_range(min: i64, max: i64, step: i64) -> CameraControl {
+        control(ControlValueDescription::IntegerRange {
+            min,
+            max,
+            value: min,
+            step,
+            default: min,
+        })
+    }
+
+    fn float_range(min: f64, max: f64, step: f64) -> CameraControl {
+        control(ControlValueDescription::FloatRange {
+            min,
+            max,
+            value: min,
+            step,
+            default: min,
+        })
+    }
+
+    fn float(setter: ControlValueSetter) -> f64 {
+        *setter.as_float().expect("a float")
+    }
+
+    #[test]
+    fn snap_rounds_half_away_from_the_origin() {
+        assert_eq!(snap_i64(4, 0, 10), 0);
+        assert_eq!(snap_i64(5, 0, 10), 10);
+        assert_eq!(snap_i64(-5, 0, 10), -10);
+        assert_eq!(snap_i64(5, 3, 4), 7);
+        assert_eq!(snap_i64(1, 3, 4), -1);
+        assert_eq!(snap_i64(i64::MAX, 0, 10), i64::MAX / 10 * 10);
+        assert_eq!(snap_i64(7, 0, 0), 7);
+
+        assert_eq!(snap_f64(0.25, 0.0, 0.5), 0.5);
+        assert_eq!(snap_f64(-0.25, 0.0, 0.5), -0.5);
+        assert_eq!(snap_f64(1.2, 1.0, 0.5), 1.0);
+        assert_eq!(snap_f64(0.3, 0.0, f64::NAN), 0.3);
+    }
+
+    #[test]
+    fn validate_snaps_into_the_range() {
+        let control = integer_range(0, 10, 4);
+        assert_eq!(
+            control.validate(ControlValueSetter::Integer(5)).unwrap(),
+            ControlValueSetter::Integer(4)
+        );
+        assert_eq!(
+            control.validate(ControlValueSetter::Integer(-3)).unwrap(),
+            ControlValueSetter::Integer(0)
+        );
+        // 10 rounds up to 12, the step below is the last one in range
+        assert_eq!(
+            control.validate(ControlValueSetter::Integer(10)).unwrap(),
+            ControlValueSetter::Integer(8)
+        );
+
+        let control = float_range(0.0, 1.0, 0.4);
+        let value = float(control.validate(ControlValueSetter::Float(1.0)).unwrap());
+        assert!((value - 0.8).abs() < 1e-9, "{value}");
+        let value = float(control.validate(ControlValueSetter::Float(-1.0)).unwrap());
+        assert_eq!(value, 0.0);
+    }
+
+    #[test]
+    fn validate_keeps_min_when_the_range_is_inverted() {
+        assert_eq!(
+            integer_range(10, 5, 1)
+                .validate(ControlValueSetter::Integer(0))
+                .unwrap(),
+            ControlValueSetter::Integer(10)
+        );
+        assert_eq!(
+            integer_range(10, 5, 1)
+                .validate(ControlValueSetter::Integer(20))
+                .unwrap(),
+            ControlValueSetter::Integer(10)
+        );
+        let value = float(
+            float_range(1.0, 0.5, 0.1)
+                .validate(ControlValueSetter::Float(0.0))
+                .unwrap(),
+        );
+        assert_eq!(value, 1.0);
+    }
+
+    #[test]
+    fn validate_takes_integers_for_enums() {
+        let control = control(ControlValueDescription::Enum {
+            value: 1,
+            possible: vec![1, 2, 3],
+            default: 1,
+        });
+        assert_eq!(
+            control.validate(ControlValueSetter::Integer(2)).unwrap(),
+            ControlValueSetter::EnumValue(2)
+        );
+        assert_eq!(
+            control.validate(ControlValueSetter::EnumValue(3)).unwrap(),
+            ControlValueSetter::EnumValue(3)
+        );
+        assert!(control.validate(ControlValueSetter::Integer(4)).is_err());
+        assert!(control.validate(ControlValueSetter::Boolean(true)).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_non_finite_values() {
+        let range = float_range(0.0, 1.0, 0.1);
+        assert!(range.validate(ControlValueSetter::Float(f64::NAN)).is_err());
+        assert!(range
+            .validate(ControlValueSetter::Float(f64::INFINITY))
+            .is_err());
+
+        let unbounded = control(ControlValueDescription::Float {
+            value: 0.0,
+            default: 0.0,
+            step: 0.1,
+        });
+        assert!(unbounded
+            .validate(ControlValueSetter::Float(f64::NEG_INFINITY))
+            .is_err());
+
+        let point = control(ControlValueDescription::Point {
+            value: (0.0, 0.0),
+            default: (0.0, 0.0),
+        });
+        assert!(point
+            .validate(ControlValueSetter::Point(0.0, f64::NAN))
+            .is_err());
+
+        let rgb = control(ControlValueDescription::RGB {
+            value: (0.0, 0.0, 0.0),
+            max: (1.0, 1.0, 1.0),
+            default: (0.0, 0.0, 0.0),
+        });
+        assert!(rgb
+            .validate(ControlValueSetter::RGB(0.5, f64::NAN, 0.5))
+            .is_err());
+    }
+
+    #[test]
+    fn validate_rejects_read_only_controls() {
+        let control = CameraControl::new(
+            KnownCameraControl::Brightness,
+            "Brightness".to_string(),
+            ControlValueDescription::Boolean {
+                value: false,
+                default: false,
+            },
+            vec![KnownCameraControlFlag::ReadOnly],
+            true,
+        );
+        assert!(control.validate(ControlValueSetter::Boolean(true)).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use crate::camera::BackgroundCamera;
+use crate::error::Result;
+use bevy::prelude::*;
+use bevy::tasks::{block_on, poll_once, AsyncComputeTaskPool, Task};
+use nokhwa::pixel_format::RgbFormat;
+use nokhwa::utils::{
+    ApiBackend, CameraFormat, CameraInfo, FrameFormat, RequestedFormat, RequestedFormatType,
+};
+use nokhwa::{nokhwa_initialize, query, Camera};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How [`AvailableCameras`] is kept up to date.
+#[derive(Resource, Debug, Clone)]
+pub struct CameraDiscoverySettings {
+    pub api: ApiBackend,
+    /// Time between two refreshes, `None` only refreshes on [`RefreshCameras`].
+    pub interval: Option<Duration>,
+    /// Open devices no [`BackgroundCamera`] is using to list their formats.
+    ///
+    /// Backends pick and apply a format when a device is opened, so this can change the format
+    /// an idle device is configured with, or fail if another process is streaming from it. The
+    /// formats of devices in use are taken from their [`BackgroundCamera`] instead.
+    pub list_formats: bool,
+}
+
+impl Default for CameraDiscoverySettings {
+    fn default() -> Self {
+        Self {
+            api: ApiBackend::Auto,
+            interval: Some(Duration::from_secs(2)),
+            list_formats: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraDevice {
+    pub info: CameraInfo,
+    /// Formats the device can stream, empty if it could not be opened to ask or
+    /// [`CameraDiscoverySettings::list_formats`] is off.
+    pub formats: Vec<CameraFormat>,
+}
+
+impl CameraDevice {
+    /// Whether both describe the same physical device, indices may shift between queries.
+    pub fn is_same_device(&self, other: &CameraInfo) -> bool {
+        let misc = self.info.misc();
+        if misc.is_empty() {
+            self.info.human_name() == other.human_name() && self.info.index() == other.index()
+        } else {
+            misc == other.misc()
+        }
+    }
+
+    /// [`CameraDevice::formats`] grouped by frame format, each sorted by resolution and then
+    /// frame rate.
+    pub fn formats_by_frame_format(&self) -> BTreeMap<FrameFormat, Vec<CameraFormat>> {
+        let mut grouped = BTreeMap::<_, Vec<_>>::new();
+        for format in &self.formats {
+            grouped.entry(format.format()).or_default().push(*format);
+        }
+        for formats in grouped.values_mut() {
+            formats.sort_by_key(|format| {
+                let resolution = format.resolution();
+                (resolution.width(), resolution.height(), format.frame_rate())
+            });
+            formats.dedup();
+        }
+        grouped
+    }
+}
+
+/// The camera devices currently attached, in the order the backend reports them.
+#[derive(Resource, Debug, Clone, Default, Deref)]
+pub struct AvailableCameras(pub Vec<CameraDevice>);
+
+/// Ask for [`AvailableCameras`] to be refreshed now.
+#[derive(Event, Debug, Clone, Default)]
+pub struct RefreshCameras;
+
+#[derive(Event, Debug, Clone)]
+pub struct CameraAdded(pub CameraDevice);
+
+#[derive(Event, Debug, Clone)]
+pub struct CameraRemoved(pub CameraDevice);
+
+#[derive(Default)]
+pub struct DiscoveryState {
+    since_refresh: Option<Duration>,
+    task: Option<Task<Result<Vec<CameraDevice>>>>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn refresh_available_cameras(
+    time: Res<Time>,
+    settings: Res<CameraDiscoverySettings>,
+    mut requests: EventReader<RefreshCameras>,
+    mut state: Local<DiscoveryState>,
+    mut cameras: ResMut<AvailableCameras>,
+    mut added: EventWriter<CameraAdded>,
+    mut removed: EventWriter<CameraRemoved>,
+    cam_query: Query<&BackgroundCamera>,
+) {
+    if let Some(task) = state.task.as_mut() {
+        let Some(result) = block_on(poll_once(task)) else {
+            return;
+        };
+        state.task = None;
+
+        match result {
+            Ok(devices) => {
+                let (new, gone) = diff(&cameras, &devices);
+                removed.send_batch(gone.into_iter().map(CameraRemoved));
+                added.send_batch(new.into_iter().map(CameraAdded));
+                if cameras.0 != devices {
+                    cameras.0 = devices;
+                }
+            }
+            Err(err) => warn!("failed to query cameras: {err}"),
+        }
+    }
+
+    let since_refresh = state.since_refresh.get_or_insert(Duration::MAX);
+    *since_refresh = since_refresh.saturating_add(time.delta());
+    let due = settings
+        .interval
+        .is_some_and(|interval| *since_refresh >= interval);
+    // requests made while a query runs are kept for the next one, it may miss a device plugged
+    // in meanwhile
+    let requested = !requests.is_empty();
+
+    if state.task.is_none() && (due || requested) {
+        requests.clear();
+        state.since_refresh = Some(Duration::ZERO);
+        let api = settings.api;
+        let list_formats = settings.list_formats;
+        let known = cameras.0.clone();
+        let in_use = cam_query
+            .iter()
+            .filter_map(|camera| camera.device().cloned())
+            .collect();
+        state.task = Some(
+            AsyncComputeTaskPool::get()
+                .spawn(async move { query_devices(api, known, in_use, list_formats) }),
+        );
+    }
+}
+
+/// Devices in `new` but not in `old`, and the other way round.
+fn diff(old: &[CameraDevice], new: &[CameraDevice]) -> (Vec<CameraDevice>, Vec<CameraDevice>) {
+    let missing_from = |devices: &[CameraDevice], device: &CameraDevice| {
+        !devices.iter().any(|d| d.is_same_device(&device.info))
+    };
+    let added = new
+        .iter()
+        .filter(|device| missing_from(old, device))
+        .cloned()
+        .collect();
+    let removed = old
+        .iter()
+        .filter(|device| missing_from(new, device))
+        .cloned()
+        .collect();
+    (added, removed)
+}
+
+fn query_devices(
+    api: ApiBackend,
+    known: Vec<CameraDevice>,
+    in_use: Vec<CameraDevice>,
+    list_formats: bool,
+) -> Result<Vec<CameraDevice>> {
+    nokhwa_initialize(|granted| {
+        debug!("camera access granted: {granted}");
+    });
+
+    let devices = query(api)?
+        .into_iter()
+        .map(|info| {
+            // opening a device to list its formats is slow and would reconfigure a running
+            // stream, ask the camera using it or only do it until we got an answer
+            let in_use = in_use.iter().find(|d| d.is_same_device(&info));
+            let known = known
+                .iter()
+                .find(|d| d.is_same_device(&info) && !d.formats.is_empty());
+            let formats = match (in_use, known) {
+                (Some(device), _) | (None, Some(device)) => device.formats.clone(),
+                (None, None) if list_formats => query_formats(&info),
+                (None, None) => Vec::new(),
+            };
+            CameraDevice { info, formats }
+        })
+        .collect();
+
+    Ok(devices)
+}
+
+fn query_formats(info: &CameraInfo) -> Vec<CameraFormat> {
+    let format = RequestedFormat::new::<RgbFormat>(RequestedFormatType::None);
+    match Camera::new(info.index().clone(), format)
+        .and_then(|mut camera| camera.compatible_camera_formats())
+    {
+        Ok(mut formats) => {
+            formats.sort();
+            formats
+        }
+        Err(err) => {
+            debug!("could not list formats of {}: {err}", info.human_name());
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nokhwa::utils::{CameraIndex, Resolution};
+
+    fn device(name: &str, misc: &str, index: u32) -> CameraDevice {
+        CameraDevice {
+            info: CameraInfo::new(name, "", misc, CameraIndex::Index(index)),
+            formats: Vec::new(),
+        }
+    }
+
+    fn names(devices: &[CameraDevice]) -> Vec<String> {
+        devices.iter().map(|d| d.info.human_name()).collect()
+    }
+
+    #[test]
+    fn unique_ids_survive_a_new_index() {
+        let old = [device("Front", "usb-1", 0), device("Back", "usb-2", 1)];
+        let new = [device("Back", "usb-2", 0), device("Side", "usb-3", 1)];
+
+        let (added, removed) = diff(&old, &new);
+        assert_eq!(names(&added), ["Side"]);
+        assert_eq!(names(&removed), ["Front"]);
+    }
+
+    #[test]
+    fn without_unique_ids_name_and_index_are_compared() {
+        let old = [device("Webcam", "", 0), device("Webcam", "", 1)];
+        let new = [device("Webcam", "", 1), device("Webcam", "", 2)];
+
+        let (added, removed) = diff(&old, &new);
+        assert_eq!(added, [device("Webcam", "", 2)]);
+        assert_eq!(removed, [device("Webcam", "", 0)]);
+
+        assert!(!device("Webcam", "", 0).is_same_device(&device("Other", "", 0).info));
+    }
+
+    #[test]
+    fn nothing_changes_between_equal_lists() {
+        let devices = [device("Front", "usb-1", 0), device("Webcam", "", 1)];
+        let (added, removed) = diff(&devices, &devices);
+        assert!(added.is_empty() && removed.is_empty());
+
+        let (added, removed) = diff(&[], &devices);
+        assert_eq!(added, devices);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn formats_are_grouped_sorted_and_unique() {
+        let format = |width, height, frame_format, frame_rate| {
+            CameraFormat::new(Resolution::new(width, height), frame_format, frame_rate)
+        };
+        let mut camera = device("Front", "usb-1", 0);
+        camera.formats = vec![
+            format(1280, 720, FrameFormat::MJPEG, 30),
+            format(640, 480, FrameFormat::YUYV, 30),
+            format(640, 480, FrameFormat::MJPEG, 60),
+            format(640, 480, FrameFormat::MJPEG, 30),
+            format(1280, 720, FrameFormat::MJPEG, 30),
+            format(640, 360, FrameFormat::MJPEG, 30),
+        ];
+
+        let grouped = camera.formats_by_frame_format();
+        assert_eq!(
+            grouped.keys().copied().collect::<Vec<_>>(),
+            [FrameFormat::MJPEG, FrameFormat::YUYV]
+        );
+        assert_eq!(
+            grouped[&FrameFormat::MJPEG],
+            [
+                format(640, 360, FrameFormat::MJPEG, 30),
+                format(640, 480, FrameFormat::MJPEG, 30),
+                format(640, 480, FrameFormat::MJPEG, 60),
+                format(1280, 720, FrameFormat::MJPEG, 30),
+            ]
+        );
+        assert_eq!(
+            grouped[&FrameFormat::YUYV],
+            [format(640, 480, FrameFormat::YUYV, 30)]
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use nokhwa::utils::KnownCameraControl;
+use nokhwa::NokhwaError;
+use thiserror::Error;
+
+pub type Result<T, E = BevyNokhwaError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Error)]
+pub enum BevyNokhwaError {
+    #[error("no camera devices found")]
+    NoDevices,
+    #[error("camera {0} not found")]
+    DeviceNotFound(String),
+    #[error("requested format is not supported: {0}")]
+    FormatUnsupported(NokhwaError),
+    #[error("failed to open camera stream: {0}")]
+    StreamOpenFailed(NokhwaError),
+    #[error("failed to capture frame: {0}")]
+    CaptureFailed(NokhwaError),
+    #[error("failed to decode frame: {0}")]
+    DecodeFailed(NokhwaError),
+    #[error("camera rejected control {id}: {error}")]
+    ControlRejected {
+        id: KnownCameraControl,
+        error: NokhwaError,
+    },
+    #[error("invalid value for control {id}: {reason}")]
+    InvalidControl {
+        id: KnownCameraControl,
+        reason: String,
+    },
+    #[error("failed to load or save presets: {0}")]
+    Preset(String),
+    #[error(transparent)]
+    Nokhwa(#[from] NokhwaError),
+}
@@ -0,0 +1,144 @@
+use crate::camera::{BackgroundCamera, CameraStatus, NegotiatedFormat};
+use crate::error::BevyNokhwaError;
+use crate::worker::WorkerEvent;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use nokhwa::utils::{CameraFormat, ControlValueSetter, KnownCameraControl};
+
+/// The camera device was opened.
+#[derive(Event, Debug, Clone)]
+pub struct CameraOpened {
+    pub entity: Entity,
+}
+
+/// The camera started capturing in a new format, also sent once after opening it.
+#[derive(Event, Debug, Clone)]
+pub struct CameraFormatChanged {
+    pub entity: Entity,
+    pub format: CameraFormat,
+}
+
+/// How the device took a control value.
+#[derive(Debug, Clone)]
+pub enum ControlOutcome {
+    Applied,
+    /// Accepted, but the device settled on another value, most likely the nearest one in range.
+    Clamped(ControlValueSetter),
+    Rejected(BevyNokhwaError),
+}
+
+/// Sent for every control value that reached the device.
+///
+/// Values superseded before the device got to them are not reported.
+#[derive(Event, Debug, Clone)]
+pub struct CameraControlResult {
+    pub entity: Entity,
+    pub id: KnownCameraControl,
+    pub value: ControlValueSetter,
+    pub outcome: ControlOutcome,
+}
+
+/// The first frame arrived after the camera was opened or resumed.
+#[derive(Event, Debug, Clone)]
+pub struct CameraStreamStarted {
+    pub entity: Entity,
+}
+
+/// A frame was captured but could not be used.
+#[derive(Event, Debug, Clone)]
+pub struct CameraFrameDropped {
+    pub entity: Entity,
+}
+
+/// The camera stopped delivering frames, most likely because it was unplugged.
+#[derive(Event, Debug, Clone)]
+pub struct CameraDisconnected {
+    pub entity: Entity,
+}
+
+/// An error reported by a camera's worker thread after construction.
+#[derive(Event, Debug, Clone)]
+pub struct CameraError {
+    pub entity: Entity,
+    pub error: BevyNokhwaError,
+}
+
+#[derive(SystemParam)]
+pub struct CameraEventWriters<'w> {
+    opened: EventWriter<'w, CameraOpened>,
+    format_changed: EventWriter<'w, CameraFormatChanged>,
+    control_result: EventWriter<'w, CameraControlResult>,
+    stream_started: EventWriter<'w, CameraStreamStarted>,
+    frame_dropped: EventWriter<'w, CameraFrameDropped>,
+    disconnected: EventWriter<'w, CameraDisconnected>,
+    error: EventWriter<'w, CameraError>,
+}
+
+pub fn handle_camera_events(
+    mut cam_query: Query<(
+        Entity,
+        &mut BackgroundCamera,
+        &mut CameraStatus,
+        &mut NegotiatedFormat,
+    )>,
+    mut events: CameraEventWriters,
+) {
+    for (entity, mut background_camera, mut status, mut negotiated) in cam_query.iter_mut() {
+        while let Ok(event) = background_camera.event_rx.try_recv() {
+            match event {
+                WorkerEvent::Opened => {
+                    status.set_if_neq(CameraStatus::Initializing);
+                    events.opened.send(CameraOpened { entity });
+                }
+                WorkerEvent::FormatChanged(format) => {
+                    negotiated.set_if_neq(NegotiatedFormat(Some(format)));
+                    events
+                        .format_changed
+                        .send(CameraFormatChanged { entity, format });
+                }
+                WorkerEvent::DeviceChanged(device) => background_camera.device_changed(device),
+                WorkerEvent::StreamStarted => {
+                    status.set_if_neq(CameraStatus::Streaming);
+                    events.stream_started.send(CameraStreamStarted { entity });
+                }
+                WorkerEvent::FrameDropped => {
+                    events.frame_dropped.send(CameraFrameDropped { entity });
+                }
+                WorkerEvent::Paused => {
+                    status.set_if_neq(CameraStatus::Paused);
+                }
+                WorkerEvent::Disconnected => {
+                    // stays here while reconnecting, becomes `Failed` if we are not
+                    status.set_if_neq(CameraStatus::Initializing);
+                    events.disconnected.send(CameraDisconnected { entity });
+                }
+                WorkerEvent::Control {
+                    id,
+                    value,
+                    outcome,
+                    current,
+                } => {
+                    if let ControlOutcome::Rejected(error) = &outcome {
+                        warn!("camera {entity}: {error}");
+                    }
+                    background_camera.control_updated(id, &value, current.map(|current| *current));
+                    events.control_result.send(CameraControlResult {
+                        entity,
+                        id,
+                        value,
+                        outcome,
+                    });
+                }
+                WorkerEvent::Error(error) => {
+                    warn!("camera {entity}: {error}");
+                    events.error.send(CameraError { entity, error });
+                }
+                WorkerEvent::Failed(error) => {
+                    error!("camera {entity} stopped: {error}");
+                    status.set_if_neq(CameraStatus::Failed);
+                    events.error.send(CameraError { entity, error });
+                }
+            }
+        }
+    }
+}
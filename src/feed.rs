@@ -0,0 +1,74 @@
+use crate::background::BackgroundImage;
+use crate::camera::BackgroundCamera;
+use crate::frame::CameraOrientation;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+/// The live frames of the [`BackgroundCamera`] on the same entity, as an [`Image`] asset.
+///
+/// Added to every camera automatically, insert it yourself to have frames written to an image
+/// you already created. The image is updated in place, so materials, sprites and UI nodes using
+/// the handle follow the feed. Frames are turned according to the [`CameraOrientation`].
+#[derive(Component, Debug, Clone)]
+pub struct CameraFeed {
+    pub image: Handle<Image>,
+}
+
+pub fn add_camera_feed(
+    mut commands: Commands,
+    new_cameras: Query<Entity, (Added<BackgroundCamera>, Without<CameraFeed>)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for entity in new_cameras.iter() {
+        let image = images.add(Image::new_fill(
+            Extent3d::default(),
+            TextureDimension::D2,
+            &[0, 0, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        ));
+        commands.entity(entity).insert(CameraFeed { image });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_camera_feed(
+    cam_query: Query<
+        (&BackgroundImage, &CameraFeed, Option<&CameraOrientation>),
+        Or<(Changed<BackgroundImage>, Changed<CameraOrientation>)>,
+    >,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (frame, feed, orientation) in cam_query.iter() {
+        let (Some(frame), Some(image)) = (&frame.0, images.get_mut(&feed.image)) else {
+            continue;
+        };
+        let orientation = orientation.copied().unwrap_or_default();
+        let (frame_width, frame_height) = frame.image.dimensions();
+        let (width, height) = orientation.oriented_size(frame_width, frame_height);
+
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        if image.texture_descriptor.size != size
+            || image.texture_descriptor.format != TextureFormat::Rgba8UnormSrgb
+        {
+            image.texture_descriptor.format = TextureFormat::Rgba8UnormSrgb;
+            image.resize(size);
+        }
+
+        if orientation.is_identity() {
+            image.data.copy_from_slice(frame.image.as_raw());
+            continue;
+        }
+        // write straight into the image instead of allocating an oriented copy
+        for (i, pixel) in image.data.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            let (x, y) = orientation.source_pixel(x, y, frame_width, frame_height);
+            pixel.copy_from_slice(&frame.image.get_pixel(x, y).0);
+        }
+    }
+}
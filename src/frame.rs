@@ -0,0 +1,422 @@
+use bevy::prelude::Component;
+use image::RgbaImage;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+
+/// A frame delivered by a [`BackgroundCamera`](crate::camera::BackgroundCamera).
+///
+/// Cloning is cheap, the pixels are shared between the capture thread, the main world and the
+/// render world.
+#[derive(Debug, Clone)]
+pub struct CameraFrame {
+    pub image: Arc<PooledImage>,
+    /// Counts up by one for every frame the camera delivered, starting at 1.
+    pub sequence: u64,
+    pub captured_at: Instant,
+}
+
+impl CameraFrame {
+    /// The frame as it is shown with `orientation`, for saving snapshots and other CPU consumers.
+    pub fn oriented(&self, orientation: CameraOrientation) -> RgbaImage {
+        orientation.apply(&self.image)
+    }
+}
+
+/// Clockwise rotation of a camera image.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+impl Rotation {
+    /// Another quarter turn clockwise.
+    pub fn clockwise(self) -> Self {
+        match self {
+            Rotation::None => Rotation::Cw90,
+            Rotation::Cw90 => Rotation::Cw180,
+            Rotation::Cw180 => Rotation::Cw270,
+            Rotation::Cw270 => Rotation::None,
+        }
+    }
+}
+
+/// How the image of the camera on the same entity is turned before it is shown.
+///
+/// The image is mirrored and flipped first, then rotated. The background and the
+/// [`CameraFeed`](crate::feed::CameraFeed) follow it, frames in
+/// [`BackgroundImage`](crate::BackgroundImage) stay as captured, see [`CameraFrame::oriented`].
+#[derive(Component, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CameraOrientation {
+    /// Swap left and right, what users of front facing cameras expect.
+    pub mirror: bool,
+    /// Swap top and bottom.
+    pub flip: bool,
+    pub rotation: Rotation,
+}
+
+impl CameraOrientation {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Size of a `width` x `height` image once oriented.
+    pub fn oriented_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.rotation {
+            Rotation::None | Rotation::Cw180 => (width, height),
+            Rotation::Cw90 | Rotation::Cw270 => (height, width),
+        }
+    }
+
+    /// Point of the captured image shown at `(u, v)` of the oriented one, both running from 0 at
+    /// the top left to 1 at the bottom right.
+    pub fn source_uv(&self, u: f32, v: f32) -> (f32, f32) {
+        let (u, v) = match self.rotation {
+            Rotation::None => (u, v),
+            Rotation::Cw90 => (v, 1.0 - u),
+            Rotation::Cw180 => (1.0 - u, 1.0 - v),
+            Rotation::Cw270 => (1.0 - v, u),
+        };
+        (
+            if self.mirror { 1.0 - u } else { u },
+            if self.flip { 1.0 - v } else { v },
+        )
+    }
+
+    /// Pixel of a captured `width` x `height` image shown at `(x, y)` of the oriented one.
+    pub fn source_pixel(&self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        let (oriented_width, oriented_height) = self.oriented_size(width, height);
+        let (x, y) = match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Cw90 => (y, oriented_width - 1 - x),
+            Rotation::Cw180 => (oriented_width - 1 - x, oriented_height - 1 - y),
+            Rotation::Cw270 => (oriented_height - 1 - y, x),
+        };
+        (
+            if self.mirror { width - 1 - x } else { x },
+            if self.flip { height - 1 - y } else { y },
+        )
+    }
+
+    pub fn apply(&self, image: &RgbaImage) -> RgbaImage {
+        if self.is_identity() {
+            return image.clone();
+        }
+        let (width, height) = image.dimensions();
+        let (oriented_width, oriented_height) = self.oriented_size(width, height);
+        RgbaImage::from_fn(oriented_width, oriented_height, |x, y| {
+            let (x, y) = self.source_pixel(x, y, width, height);
+            *image.get_pixel(x, y)
+        })
+    }
+}
+
+/// What the capture thread does when the app is not taking frames as fast as they come in.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop the oldest queued frame to make room, keeps latency low.
+    #[default]
+    KeepNewest,
+    /// Drop the frame that was just captured.
+    KeepOldest,
+    /// Stop capturing until there is room, the device may drop frames on its own meanwhile.
+    Block,
+}
+
+/// How many frames may be waiting for the app per camera, and what happens past that.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameQueue {
+    /// At least 1.
+    pub depth: usize,
+    pub policy: DropPolicy,
+}
+
+impl Default for FrameQueue {
+    fn default() -> Self {
+        Self {
+            depth: 2,
+            policy: DropPolicy::KeepNewest,
+        }
+    }
+}
+
+/// Frame counters of a camera since it was created.
+///
+/// `dropped` only counts frames lost to the [`FrameQueue`] or skipped because a newer one was
+/// waiting, frames that failed to decode are reported through
+/// [`CameraFrameDropped`](crate::event::CameraFrameDropped) instead.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub produced: u64,
+    pub consumed: u64,
+    pub dropped: u64,
+}
+
+/// [`FrameStats`] shared between the capture thread and the main world.
+#[derive(Debug, Default)]
+pub(crate) struct FrameCounters {
+    produced: AtomicU64,
+    consumed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl FrameCounters {
+    pub fn produced(&self) {
+        self.produced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn consumed(&self) {
+        self.consumed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dropped(&self, count: u64) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            produced: self.produced.load(Ordering::Relaxed),
+            consumed: self.consumed.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Spare buffers kept around per camera, more are freed instead of recycled.
+const MAX_POOLED: usize = 4;
+
+type FreeList = Mutex<Vec<RgbaImage>>;
+
+/// Recycles frame buffers, so capturing does not allocate once frames are being released as
+/// fast as they come in.
+#[derive(Default)]
+pub(crate) struct FramePool {
+    free: Arc<FreeList>,
+}
+
+impl FramePool {
+    /// A buffer to capture into, it may still hold an older frame of any size.
+    pub fn take(&self) -> RgbaImage {
+        self.free.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    pub fn recycle(&self, image: RgbaImage) {
+        recycle(&self.free, image);
+    }
+
+    pub fn share(&self, image: RgbaImage) -> Arc<PooledImage> {
+        Arc::new(PooledImage {
+            image,
+            pool: Arc::downgrade(&self.free),
+        })
+    }
+}
+
+fn recycle(free: &FreeList, image: RgbaImage) {
+    let mut free = free.lock().unwrap();
+    if free.len() < MAX_POOLED {
+        free.push(image);
+    }
+}
+
+/// Frame pixels that go back to their camera's pool once the last reference is dropped.
+#[derive(Debug)]
+pub struct PooledImage {
+    image: RgbaImage,
+    pool: Weak<FreeList>,
+}
+
+impl Deref for PooledImage {
+    type Target = RgbaImage;
+
+    fn deref(&self) -> &Self::Target {
+        &self.image
+    }
+}
+
+impl Drop for PooledImage {
+    fn drop(&mut self) {
+        if let Some(free) = self.pool.upgrade() {
+            recycle(&free, std::mem::take(&mut self.image));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const ROTATIONS: [Rotation; 4] = [
+        Rotation::None,
+        Rotation::Cw90,
+        Rotation::Cw180,
+        Rotation::Cw270,
+    ];
+
+    fn orientations() -> impl Iterator<Item = CameraOrientation> {
+        ROTATIONS.into_iter().flat_map(|rotation| {
+            [(false, false), (true, false), (false, true), (true, true)]
+                .into_iter()
+                .map(move |(mirror, flip)| CameraOrientation {
+                    mirror,
+                    flip,
+                    rotation,
+                })
+        })
+    }
+
+    fn rotated(rotation: Rotation) -> CameraOrientation {
+        CameraOrientation {
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    /// The letters `a` to `f`, two per row:
+    ///
+    /// ```text
+    /// a b
+    /// c d
+    /// e f
+    /// ```
+    fn image() -> RgbaImage {
+        RgbaImage::from_fn(2, 3, |x, y| Rgba([b'a' + (y * 2 + x) as u8, 0, 0, 255]))
+    }
+
+    fn rows(image: &RgbaImage) -> Vec<String> {
+        image
+            .rows()
+            .map(|row| row.map(|pixel| pixel[0] as char).collect())
+            .collect()
+    }
+
+    #[test]
+    fn apply_turns_the_image() {
+        let image = image();
+        let cases = [
+            (CameraOrientation::default(), ["ab", "cd", "ef"].as_slice()),
+            (
+                CameraOrientation {
+                    mirror: true,
+                    ..Default::default()
+                },
+                &["ba", "dc", "fe"],
+            ),
+            (
+                CameraOrientation {
+                    flip: true,
+                    ..Default::default()
+                },
+                &["ef", "cd", "ab"],
+            ),
+            (rotated(Rotation::Cw90), &["eca", "fdb"]),
+            (rotated(Rotation::Cw180), &["fe", "dc", "ba"]),
+            (rotated(Rotation::Cw270), &["bdf", "ace"]),
+            (
+                // mirrored first, then turned
+                CameraOrientation {
+                    mirror: true,
+                    rotation: Rotation::Cw90,
+                    ..Default::default()
+                },
+                &["fdb", "eca"],
+            ),
+        ];
+        for (orientation, expected) in cases {
+            assert_eq!(
+                rows(&orientation.apply(&image)),
+                expected,
+                "{orientation:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn oriented_size_matches_apply() {
+        let image = image();
+        for orientation in orientations() {
+            assert_eq!(
+                orientation.apply(&image).dimensions(),
+                orientation.oriented_size(2, 3),
+                "{orientation:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn source_uv_matches_source_pixel_at_pixel_centers() {
+        let (width, height) = (2, 3);
+        for orientation in orientations() {
+            let (oriented_width, oriented_height) = orientation.oriented_size(width, height);
+            for y in 0..oriented_height {
+                for x in 0..oriented_width {
+                    let (source_x, source_y) = orientation.source_pixel(x, y, width, height);
+                    let (u, v) = orientation.source_uv(
+                        (x as f32 + 0.5) / oriented_width as f32,
+                        (y as f32 + 0.5) / oriented_height as f32,
+                    );
+                    assert!(
+                        (u - (source_x as f32 + 0.5) / width as f32).abs() < 1e-6
+                            && (v - (source_y as f32 + 0.5) / height as f32).abs() < 1e-6,
+                        "{orientation:?} at {x}, {y}: ({u}, {v}) is not pixel {source_x}, {source_y}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn turning_back_restores_the_image() {
+        let image = image();
+        for orientation in orientations() {
+            // finish the full turn, then undo the mirror and flip
+            let quarter_turns = ROTATIONS
+                .iter()
+                .position(|rotation| *rotation == orientation.rotation)
+                .unwrap();
+            let mut back = orientation.apply(&image);
+            for _ in quarter_turns..4 {
+                back = rotated(Rotation::Cw90).apply(&back);
+            }
+            let back = CameraOrientation {
+                mirror: orientation.mirror,
+                flip: orientation.flip,
+                ..Default::default()
+            }
+            .apply(&back);
+            assert_eq!(back, image, "{orientation:?}");
+        }
+
+        let mut turned = image.clone();
+        for _ in 0..4 {
+            turned = rotated(Rotation::Cw90).apply(&turned);
+        }
+        assert_eq!(turned, image);
+    }
+
+    #[test]
+    fn mirror_and_flip_is_a_half_turn() {
+        let image = image();
+        let both = CameraOrientation {
+            mirror: true,
+            flip: true,
+            ..Default::default()
+        };
+        assert_eq!(both.apply(&image), rotated(Rotation::Cw180).apply(&image));
+    }
+
+    #[test]
+    fn clockwise_cycles_through_all_rotations() {
+        let mut rotation = Rotation::None;
+        for expected in ROTATIONS.into_iter().cycle().skip(1).take(4) {
+            rotation = rotation.clockwise();
+            assert_eq!(rotation, expected);
+        }
+    }
+}
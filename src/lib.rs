@@ -1,29 +1,97 @@
 use crate::background::{
-    handle_background_image, BackgroundImage, BackgroundNode, BackgroundNodeLabel,
-    BackgroundPipeline,
+    extract_background_images, handle_background_image, prepare_background_textures,
+    prepare_view_backgrounds, BackgroundNode, BackgroundNodeLabel, BackgroundPipeline,
+    BackgroundTextures, ExtractedBackgroundImages, ViewBackgrounds, BACKGROUND_SHADER_HANDLE,
 };
+use crate::camera::stop_cameras_on_exit;
+use crate::discovery::{
+    refresh_available_cameras, AvailableCameras, CameraAdded, CameraDiscoverySettings,
+    CameraRemoved, RefreshCameras,
+};
+use crate::event::{
+    handle_camera_events, CameraControlResult, CameraDisconnected, CameraError,
+    CameraFormatChanged, CameraFrameDropped, CameraOpened, CameraStreamStarted,
+};
+use crate::feed::{add_camera_feed, update_camera_feed};
+use bevy::asset::load_internal_asset;
 use bevy::core_pipeline;
 use bevy::prelude::*;
-use bevy::render::extract_resource::ExtractResourcePlugin;
+use bevy::render::extract_component::ExtractComponentPlugin;
 
 use bevy::render::render_graph::RenderGraph;
-use bevy::render::RenderApp;
-use image::RgbaImage;
+use bevy::render::render_resource::SpecializedRenderPipelines;
+use bevy::render::{ExtractSchedule, Render, RenderApp, RenderSet};
 
+pub use background::{BackgroundFit, BackgroundImage, ShowCameraBackground};
 pub use nokhwa;
 
 mod background;
 pub mod camera;
+pub mod control;
+pub mod discovery;
+pub mod error;
+pub mod event;
+pub mod feed;
+pub mod frame;
+#[cfg(feature = "egui")]
+pub mod panel;
+#[cfg(feature = "serde")]
+pub mod preset;
+pub mod selector;
+pub mod source;
+mod worker;
 
 pub struct BevyNokhwaPlugin;
 
 impl Plugin for BevyNokhwaPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(BackgroundImage(RgbaImage::new(640, 480)))
-            .add_plugins(ExtractResourcePlugin::<BackgroundImage>::default())
-            .add_systems(Update, handle_background_image);
+        load_internal_asset!(
+            app,
+            BACKGROUND_SHADER_HANDLE,
+            "shader.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.add_plugins((
+            ExtractComponentPlugin::<ShowCameraBackground>::default(),
+            ExtractComponentPlugin::<BackgroundFit>::default(),
+        ))
+        .add_event::<CameraOpened>()
+        .add_event::<CameraFormatChanged>()
+        .add_event::<CameraControlResult>()
+        .add_event::<CameraStreamStarted>()
+        .add_event::<CameraFrameDropped>()
+        .add_event::<CameraDisconnected>()
+        .add_event::<CameraError>()
+        .init_resource::<CameraDiscoverySettings>()
+        .init_resource::<AvailableCameras>()
+        .add_event::<RefreshCameras>()
+        .add_event::<CameraAdded>()
+        .add_event::<CameraRemoved>()
+        .add_systems(Update, refresh_available_cameras)
+        .add_systems(
+            Update,
+            (
+                (add_camera_feed, handle_background_image, update_camera_feed).chain(),
+                handle_camera_events,
+            ),
+        )
+        .add_systems(Last, stop_cameras_on_exit);
 
         let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .init_resource::<ExtractedBackgroundImages>()
+            .init_resource::<BackgroundTextures>()
+            .init_resource::<ViewBackgrounds>()
+            .init_resource::<SpecializedRenderPipelines<BackgroundPipeline>>()
+            .add_systems(ExtractSchedule, extract_background_images)
+            .add_systems(
+                Render,
+                (
+                    prepare_view_backgrounds.in_set(RenderSet::PrepareResources),
+                    prepare_background_textures.in_set(RenderSet::PrepareBindGroups),
+                ),
+            );
 
         let background_node_2d = BackgroundNode::new(render_app.world_mut());
         let background_node_3d = BackgroundNode::new(render_app.world_mut());
@@ -34,9 +102,14 @@ impl Plugin for BevyNokhwaPlugin {
         {
             graph_2d.add_node(BackgroundNodeLabel, background_node_2d);
 
+            // drawn behind opaque geometry by the depth test, transparent things blend over it
+            graph_2d.add_node_edge(
+                core_pipeline::core_2d::graph::Node2d::MainOpaquePass,
+                BackgroundNodeLabel,
+            );
             graph_2d.add_node_edge(
                 BackgroundNodeLabel,
-                core_pipeline::core_2d::graph::Node2d::StartMainPass,
+                core_pipeline::core_2d::graph::Node2d::MainTransparentPass,
             );
         }
 
@@ -45,9 +118,13 @@ impl Plugin for BevyNokhwaPlugin {
         {
             graph_3d.add_node(BackgroundNodeLabel, background_node_3d);
 
+            graph_3d.add_node_edge(
+                core_pipeline::core_3d::graph::Node3d::MainOpaquePass,
+                BackgroundNodeLabel,
+            );
             graph_3d.add_node_edge(
                 BackgroundNodeLabel,
-                core_pipeline::core_3d::graph::Node3d::MainTransparentPass,
+                core_pipeline::core_3d::graph::Node3d::MainTransmissivePass,
             );
         }
     }
@@ -0,0 +1,440 @@
+use crate::camera::{BackgroundCamera, CameraStatus, NegotiatedFormat};
+use crate::control::CameraControlExt;
+use crate::discovery::{AvailableCameras, CameraDevice, CameraDiscoverySettings};
+use crate::error::Result;
+use crate::feed::CameraFeed;
+use crate::frame::CameraOrientation;
+use crate::selector::CameraSelector;
+use bevy::prelude::*;
+use bevy::tasks::{block_on, poll_once, AsyncComputeTaskPool, Task};
+use bevy::utils::HashMap;
+use bevy_egui::egui;
+use bevy_egui::{EguiContexts, EguiPlugin};
+use nokhwa::utils::{
+    ApiBackend, CameraControl, CameraFormat, ControlValueDescription, ControlValueSetter,
+    RequestedFormatType,
+};
+use std::time::Duration;
+
+/// Shows a [`CameraPanel`] window for every [`BackgroundCamera`], adds the [`EguiPlugin`] if
+/// it is missing.
+pub struct CameraPanelPlugin;
+
+impl Plugin for CameraPanelPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        app.add_systems(Update, show_camera_panels);
+    }
+}
+
+/// Device picker, format picker, controls, frame counters and a thumbnail of one camera.
+///
+/// Put it in your own egui layout, or let [`CameraPanelPlugin`] show one per camera.
+pub struct CameraPanel<'a> {
+    pub camera: &'a mut BackgroundCamera,
+    pub status: CameraStatus,
+    pub format: Option<CameraFormat>,
+    /// How the thumbnail is turned, it follows the camera's [`CameraOrientation`].
+    pub orientation: CameraOrientation,
+    /// Devices offered by the device picker, opened through `api`.
+    pub available: &'a [CameraDevice],
+    pub api: ApiBackend,
+    /// The camera's [`CameraFeed`] registered with [`EguiContexts::add_image`].
+    pub thumbnail: Option<egui::TextureId>,
+    pub fps: f32,
+    pub state: &'a mut CameraPanelState,
+}
+
+/// What a [`CameraPanel`] keeps between frames, one per camera.
+#[derive(Default)]
+pub struct CameraPanelState {
+    /// Name of the device picked last and the task opening it, swapped in once it is done.
+    opening: Option<(String, Task<Result<BackgroundCamera>>)>,
+}
+
+const THUMBNAIL_WIDTH: f32 = 240.0;
+
+impl CameraPanel<'_> {
+    pub fn show(mut self, ui: &mut egui::Ui) {
+        self.finish_opening();
+
+        if let Some(texture) = self.thumbnail {
+            let aspect = self.format.map_or(0.75, |format| {
+                let (width, height) = self
+                    .orientation
+                    .oriented_size(format.width(), format.height());
+                height as f32 / width.max(1) as f32
+            });
+            ui.image(egui::load::SizedTexture::new(
+                texture,
+                egui::vec2(THUMBNAIL_WIDTH, THUMBNAIL_WIDTH * aspect),
+            ));
+        }
+
+        let stats = self.camera.frame_stats();
+        ui.label(format!(
+            "{:?}, {:.1} fps, {} dropped",
+            self.status, self.fps, stats.dropped
+        ));
+
+        self.device_picker(ui);
+        self.format_picker(ui);
+        ui.separator();
+        self.controls(ui);
+    }
+
+    fn device_picker(&mut self, ui: &mut egui::Ui) {
+        let current = self.camera.device();
+        let mut selected = None;
+        ui.add_enabled_ui(self.state.opening.is_none(), |ui| {
+            egui::ComboBox::from_label("Device")
+                .selected_text(current.map_or_else(String::new, |device| device.info.human_name()))
+                .show_ui(ui, |ui| {
+                    for device in self.available {
+                        let is_current =
+                            current.is_some_and(|current| device.is_same_device(&current.info));
+                        if ui
+                            .selectable_label(is_current, device.info.human_name())
+                            .clicked()
+                            && !is_current
+                        {
+                            selected = Some(device);
+                        }
+                    }
+                });
+        });
+        if let Some((name, _)) = &self.state.opening {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("Opening {name}"));
+            });
+        }
+
+        let Some(device) = selected else {
+            return;
+        };
+        let misc = device.info.misc();
+        let selector = if misc.is_empty() {
+            CameraSelector::Index(device.info.index().clone())
+        } else {
+            CameraSelector::UniqueId(misc)
+        };
+        // opening a device and negotiating its format takes a while, keep the app running
+        let api = self.api;
+        let task = AsyncComputeTaskPool::get()
+            .spawn(async move { BackgroundCamera::select(api, [selector], None) });
+        self.state.opening = Some((device.info.human_name(), task));
+    }
+
+    /// Swap in the camera picked in the device picker once it is open.
+    fn finish_opening(&mut self) {
+        let Some((name, task)) = self.state.opening.as_mut() else {
+            return;
+        };
+        let Some(result) = block_on(poll_once(task)) else {
+            return;
+        };
+        let name = std::mem::take(name);
+        self.state.opening = None;
+
+        match result {
+            Ok(camera) => {
+                let queue = self.camera.frame_queue();
+                let reconnect = self.camera.reconnect().cloned();
+                let old = std::mem::replace(self.camera, camera.with_frame_queue(queue));
+                self.camera.set_reconnect(reconnect);
+                // stopping waits for the device to be released, do that off the main thread too
+                AsyncComputeTaskPool::get()
+                    .spawn(async move { drop(old) })
+                    .detach();
+            }
+            Err(err) => warn!("failed to open {name}: {err}"),
+        }
+    }
+
+    fn format_picker(&mut self, ui: &mut egui::Ui) {
+        let Some(device) = self.camera.device() else {
+            return;
+        };
+
+        let mut selected = None;
+        egui::ComboBox::from_label("Format")
+            .selected_text(self.format.map_or_else(String::new, format_label))
+            .show_ui(ui, |ui| {
+                for (frame_format, formats) in device.formats_by_frame_format() {
+                    ui.label(frame_format.to_string());
+                    for format in formats {
+                        if ui
+                            .selectable_label(self.format == Some(format), format_label(format))
+                            .clicked()
+                        {
+                            selected = Some(format);
+                        }
+                    }
+                }
+            });
+
+        if let Some(format) = selected.filter(|format| Some(*format) != self.format) {
+            self.camera.set_format(RequestedFormatType::Exact(format));
+        }
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        if self.camera.known_controls.is_empty() {
+            return;
+        }
+
+        let known_controls = self.camera.known_controls.clone();
+        egui::Grid::new("camera controls")
+            .num_columns(3)
+            .show(ui, |ui| {
+                for (id, control) in &known_controls {
+                    if control.is_automatic() {
+                        ui.label(format!("{id} (auto)"));
+                    } else {
+                        ui.label(id.to_string());
+                    }
+                    ui.add_enabled_ui(!control.is_read_only(), |ui| {
+                        if let Some(value) = control_ui(ui, self.camera, control) {
+                            if let Err(err) = self.camera.set_control(*id, value) {
+                                warn!("{err}");
+                            }
+                        }
+                    });
+                    if ui
+                        .add_enabled(!control.is_read_only(), egui::Button::new("Reset"))
+                        .clicked()
+                    {
+                        let _ = self.camera.reset_control(*id);
+                    }
+                    ui.end_row();
+                }
+            });
+
+        if ui.button("Reset all").clicked() {
+            for (id, control) in &known_controls {
+                if !control.is_read_only() {
+                    let _ = self.camera.reset_control(*id);
+                }
+            }
+        }
+    }
+}
+
+fn format_label(format: CameraFormat) -> String {
+    format!(
+        "{}x{} @ {} fps",
+        format.width(),
+        format.height(),
+        format.frame_rate()
+    )
+}
+
+/// The widget fitting the control, returns the new value if it was changed.
+fn control_ui(
+    ui: &mut egui::Ui,
+    camera: &BackgroundCamera,
+    control: &CameraControl,
+) -> Option<ControlValueSetter> {
+    let id = control.control();
+    match control.description() {
+        ControlValueDescription::Integer { .. } | ControlValueDescription::IntegerRange { .. } => {
+            let mut value = camera.control_value::<i64>(id)?;
+            let changed = match control.range::<i64>() {
+                Some(range) => ui.add(
+                    egui::Slider::new(&mut value, range)
+                        .step_by(control.step::<i64>().unwrap_or(1) as f64),
+                ),
+                None => ui.add(egui::DragValue::new(&mut value)),
+            }
+            .changed();
+            changed.then_some(ControlValueSetter::Integer(value))
+        }
+        ControlValueDescription::Float { .. } | ControlValueDescription::FloatRange { .. } => {
+            let mut value = camera.control_value::<f64>(id)?;
+            let step = control.step::<f64>().unwrap_or(0.0);
+            let changed = match control.range::<f64>() {
+                Some(range) => ui.add(egui::Slider::new(&mut value, range).step_by(step)),
+                None => ui.add(egui::DragValue::new(&mut value).speed(step.max(0.01))),
+            }
+            .changed();
+            changed.then_some(ControlValueSetter::Float(value))
+        }
+        ControlValueDescription::Boolean { .. } => {
+            let mut value = camera.control_value::<bool>(id)?;
+            ui.checkbox(&mut value, "")
+                .changed()
+                .then_some(ControlValueSetter::Boolean(value))
+        }
+        ControlValueDescription::Enum { .. } => {
+            let mut value = camera.control_value::<i64>(id)?;
+            let mut changed = false;
+            egui::ComboBox::from_id_salt(id.to_string())
+                .selected_text(value.to_string())
+                .show_ui(ui, |ui| {
+                    for option in control.options().unwrap_or_default() {
+                        changed |= ui
+                            .selectable_value(&mut value, *option, option.to_string())
+                            .changed();
+                    }
+                });
+            changed.then_some(ControlValueSetter::EnumValue(value))
+        }
+        ControlValueDescription::String { .. } => {
+            // the text being edited lives in egui's memory, the control only changes on Enter
+            let edit_id = ui.make_persistent_id(("edit", id.to_string()));
+            let mut value = match ui.data_mut(|data| data.get_temp::<String>(edit_id)) {
+                Some(value) => value,
+                None => camera.control_value::<String>(id)?,
+            };
+            let response = ui.text_edit_singleline(&mut value);
+            if response.lost_focus() {
+                ui.data_mut(|data| data.remove::<String>(edit_id));
+                ui.input(|input| input.key_pressed(egui::Key::Enter))
+                    .then_some(ControlValueSetter::String(value))
+            } else {
+                if response.has_focus() {
+                    ui.data_mut(|data| data.insert_temp(edit_id, value));
+                }
+                None
+            }
+        }
+        ControlValueDescription::Point { .. } => {
+            let (mut x, mut y) = camera.control_value::<(f64, f64)>(id)?;
+            let changed = ui
+                .horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut x).prefix("x ")).changed()
+                        | ui.add(egui::DragValue::new(&mut y).prefix("y ")).changed()
+                })
+                .inner;
+            changed.then_some(ControlValueSetter::Point(x, y))
+        }
+        ControlValueDescription::RGB { max, .. } => {
+            let (mut r, mut g, mut b) = camera.control_value::<(f64, f64, f64)>(id)?;
+            let changed = ui
+                .vertical(|ui| {
+                    ui.add(egui::Slider::new(&mut r, 0.0..=max.0).text("r"))
+                        .changed()
+                        | ui.add(egui::Slider::new(&mut g, 0.0..=max.1).text("g"))
+                            .changed()
+                        | ui.add(egui::Slider::new(&mut b, 0.0..=max.2).text("b"))
+                            .changed()
+                })
+                .inner;
+            changed.then_some(ControlValueSetter::RGB(r, g, b))
+        }
+        ControlValueDescription::KeyValuePair { key, value, .. } => {
+            ui.label(format!("{key}: {value}"));
+            None
+        }
+        ControlValueDescription::Bytes { value, .. } => {
+            ui.label(format!("{} bytes", value.len()));
+            None
+        }
+        ControlValueDescription::None => {
+            ui.label("");
+            None
+        }
+    }
+}
+
+/// Frame rate over the last half second, from the number of frames produced.
+#[derive(Default)]
+struct FpsCounter {
+    since: Option<Duration>,
+    produced: u64,
+    fps: f32,
+}
+
+impl FpsCounter {
+    fn update(&mut self, now: Duration, produced: u64) -> f32 {
+        match self.since {
+            Some(since) if now - since < Duration::from_millis(500) => {}
+            Some(since) => {
+                let frames = produced.saturating_sub(self.produced);
+                self.fps = frames as f32 / (now - since).as_secs_f32();
+                self.since = Some(now);
+                self.produced = produced;
+            }
+            None => {
+                self.since = Some(now);
+                self.produced = produced;
+            }
+        }
+        self.fps
+    }
+}
+
+/// What [`show_camera_panels`] keeps per camera.
+#[derive(Default)]
+struct Panel {
+    state: CameraPanelState,
+    fps: FpsCounter,
+    /// The [`CameraFeed`] registered with egui.
+    thumbnail: Option<Handle<Image>>,
+}
+
+#[allow(clippy::type_complexity)]
+fn show_camera_panels(
+    mut contexts: EguiContexts,
+    time: Res<Time>,
+    settings: Res<CameraDiscoverySettings>,
+    available: Res<AvailableCameras>,
+    mut cam_query: Query<(
+        Entity,
+        &mut BackgroundCamera,
+        &CameraStatus,
+        &NegotiatedFormat,
+        &CameraOrientation,
+        Option<&CameraFeed>,
+    )>,
+    mut panels: Local<HashMap<Entity, Panel>>,
+) {
+    panels.retain(|entity, panel| {
+        let keep = cam_query.contains(*entity);
+        if let Some(image) = panel.thumbnail.as_ref().filter(|_| !keep) {
+            contexts.remove_image(image);
+        }
+        keep
+    });
+
+    for (entity, mut camera, status, format, orientation, feed) in cam_query.iter_mut() {
+        let panel = panels.entry(entity).or_default();
+        let image = feed.map(|feed| feed.image.clone_weak());
+        if panel.thumbnail != image {
+            if let Some(old) = panel.thumbnail.take() {
+                contexts.remove_image(&old);
+            }
+            panel.thumbnail = image;
+        }
+        let thumbnail = panel
+            .thumbnail
+            .clone()
+            .map(|image| contexts.add_image(image));
+        let fps = panel
+            .fps
+            .update(time.elapsed(), camera.frame_stats().produced);
+        let title = camera
+            .device()
+            .map_or_else(|| "Camera".to_string(), |device| device.info.human_name());
+
+        egui::Window::new(title)
+            .id(egui::Id::new(entity))
+            .show(contexts.ctx_mut(), |ui| {
+                CameraPanel {
+                    camera: &mut camera,
+                    status: *status,
+                    format: format.0,
+                    orientation: *orientation,
+                    available: &available,
+                    api: settings.api,
+                    thumbnail,
+                    fps,
+                    state: &mut panel.state,
+                }
+                .show(ui);
+            });
+    }
+}
@@ -0,0 +1,470 @@
+use crate::camera::{BackgroundCamera, NegotiatedFormat};
+use crate::control::CameraControlExt;
+use crate::error::{BevyNokhwaError, Result};
+use bevy::prelude::*;
+use nokhwa::utils::{
+    CameraFormat, CameraInfo, ControlValueSetter, KnownCameraControl, RequestedFormatType,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Loads [`CameraPresets`] from `path` on startup and keeps [`ActivePreset`]s applied.
+pub struct CameraPresetPlugin {
+    /// `.json` files are read and written as JSON, anything else as RON.
+    pub path: PathBuf,
+}
+
+impl Plugin for CameraPresetPlugin {
+    fn build(&self, app: &mut App) {
+        let presets = CameraPresets::load(&self.path).unwrap_or_else(|err| {
+            // a missing file is created on the first save
+            if self.path.exists() {
+                warn!("{err}");
+            }
+            CameraPresets {
+                path: self.path.clone(),
+                file: PresetFile::default(),
+            }
+        });
+
+        app.insert_resource(presets)
+            .add_event::<SaveCameraPreset>()
+            .add_systems(Update, (save_camera_presets, apply_active_presets).chain());
+    }
+}
+
+/// Control values and capture format of a camera.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CameraPreset {
+    pub format: Option<CameraFormat>,
+    #[serde(with = "controls_as_list")]
+    pub controls: BTreeMap<KnownCameraControl, ControlValueSetter>,
+}
+
+impl CameraPreset {
+    /// The current values of all writable controls of `camera`.
+    pub fn capture(camera: &BackgroundCamera, format: Option<CameraFormat>) -> Self {
+        let controls = camera
+            .controls
+            .iter()
+            .filter(|(id, _)| {
+                camera
+                    .control(**id)
+                    .is_some_and(|control| !control.is_read_only())
+            })
+            .map(|(id, value)| (*id, value.clone()))
+            .collect();
+        Self { format, controls }
+    }
+
+    /// Switch `camera` to the preset format and set every control in the preset.
+    ///
+    /// Controls the camera does not have or rejects are skipped, the error of the last one is
+    /// returned.
+    pub fn apply(&self, camera: &mut BackgroundCamera) -> Result<()> {
+        if let Some(format) = self.format {
+            camera.set_format(RequestedFormatType::Closest(format));
+        }
+
+        let mut result = Ok(());
+        for (id, value) in &self.controls {
+            if let Err(err) = camera.set_control(*id, value.clone()) {
+                result = Err(err);
+            }
+        }
+        result
+    }
+}
+
+/// Named presets per device.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PresetFile {
+    /// Keyed by [`device_key`], then by preset name.
+    pub devices: BTreeMap<String, BTreeMap<String, CameraPreset>>,
+}
+
+/// Identifies a device across launches, its unique id if the backend has one and its name
+/// otherwise.
+pub fn device_key(info: &CameraInfo) -> String {
+    let misc = info.misc();
+    if misc.is_empty() {
+        info.human_name()
+    } else {
+        misc
+    }
+}
+
+/// The presets of all devices and the file they are saved to.
+#[derive(Resource, Debug, Clone)]
+pub struct CameraPresets {
+    pub path: PathBuf,
+    pub file: PresetFile,
+}
+
+impl CameraPresets {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|err| preset_error(path, err))?;
+        let file = if is_json(path) {
+            serde_json::from_str(&text).map_err(|err| preset_error(path, err))?
+        } else {
+            ron::from_str(&text).map_err(|err| preset_error(path, err))?
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = &self.path;
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(&self.file).map_err(|err| preset_error(path, err))?
+        } else {
+            ron::ser::to_string_pretty(&self.file, ron::ser::PrettyConfig::default())
+                .map_err(|err| preset_error(path, err))?
+        };
+        std::fs::write(path, text).map_err(|err| preset_error(path, err))
+    }
+
+    /// The preset called `name` for the device behind `camera`.
+    pub fn get(&self, camera: &BackgroundCamera, name: &str) -> Option<&CameraPreset> {
+        let device = camera.device()?;
+        self.file.devices.get(&device_key(&device.info))?.get(name)
+    }
+
+    /// Names of the presets stored for the device behind `camera`.
+    pub fn names(&self, camera: &BackgroundCamera) -> impl Iterator<Item = &str> {
+        camera
+            .device()
+            .and_then(|device| self.file.devices.get(&device_key(&device.info)))
+            .into_iter()
+            .flat_map(|presets| presets.keys().map(String::as_str))
+    }
+
+    /// Store `preset` as `name` for the device behind `camera`, replacing any preset of that name.
+    pub fn insert(
+        &mut self,
+        camera: &BackgroundCamera,
+        name: impl Into<String>,
+        preset: CameraPreset,
+    ) {
+        let Some(device) = camera.device() else {
+            return;
+        };
+        self.file
+            .devices
+            .entry(device_key(&device.info))
+            .or_default()
+            .insert(name.into(), preset);
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+fn preset_error(path: &Path, err: impl std::fmt::Display) -> BevyNokhwaError {
+    BevyNokhwaError::Preset(format!("{}: {err}", path.display()))
+}
+
+/// The preset applied to the camera on this entity, change it to switch presets.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct ActivePreset(pub String);
+
+/// Capture the current controls and format of a camera as a named preset and write the presets
+/// to disk.
+#[derive(Event, Debug, Clone)]
+pub struct SaveCameraPreset {
+    pub entity: Entity,
+    pub name: String,
+}
+
+fn save_camera_presets(
+    mut requests: EventReader<SaveCameraPreset>,
+    mut presets: ResMut<CameraPresets>,
+    cam_query: Query<(&BackgroundCamera, &NegotiatedFormat)>,
+) {
+    let mut changed = false;
+    for request in requests.read() {
+        let Ok((camera, format)) = cam_query.get(request.entity) else {
+            continue;
+        };
+        let preset = CameraPreset::capture(camera, format.0);
+        presets.insert(camera, request.name.clone(), preset);
+        changed = true;
+    }
+
+    if changed {
+        if let Err(err) = presets.save() {
+            warn!("{err}");
+        }
+    }
+}
+
+fn apply_active_presets(
+    presets: Res<CameraPresets>,
+    mut cam_query: Query<(Entity, &mut BackgroundCamera, Ref<ActivePreset>)>,
+) {
+    for (entity, mut camera, active) in cam_query.iter_mut() {
+        if !active.is_changed() {
+            continue;
+        }
+        let Some(preset) = presets.get(&camera, &active.0) else {
+            warn!("camera {entity}: no preset named {}", active.0);
+            continue;
+        };
+        if let Err(err) = preset.apply(&mut camera) {
+            warn!("camera {entity}: {err}");
+        }
+    }
+}
+
+/// Control ids are not all strings, which JSON requires of map keys.
+mod controls_as_list {
+    use nokhwa::utils::{ControlValueSetter, KnownCameraControl};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S: Serializer>(
+        controls: &BTreeMap<KnownCameraControl, ControlValueSetter>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        controls.iter().collect::<Vec<_>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<KnownCameraControl, ControlValueSetter>, D::Error> {
+        Ok(
+            Vec::<(KnownCameraControl, ControlValueSetter)>::deserialize(deserializer)?
+                .into_iter()
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::CameraDevice;
+    use crate::source::FrameSource;
+    use image::RgbaImage;
+    use nokhwa::utils::{
+        CameraControl, CameraIndex, ControlValueDescription, FrameFormat, KnownCameraControlFlag,
+        Resolution,
+    };
+
+    /// A file in the temp dir, removed again when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let name = format!("bevy_nokhwa_{}_{name}", std::process::id());
+            Self(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Every kind of value, with the extremes of the 128 bit integers.
+    fn preset_file() -> PresetFile {
+        let controls = BTreeMap::from([
+            (
+                KnownCameraControl::Brightness,
+                ControlValueSetter::Integer(-3),
+            ),
+            (KnownCameraControl::Gamma, ControlValueSetter::Float(0.1)),
+            (
+                KnownCameraControl::BacklightComp,
+                ControlValueSetter::Boolean(true),
+            ),
+            (
+                KnownCameraControl::WhiteBalance,
+                ControlValueSetter::EnumValue(2),
+            ),
+            (
+                KnownCameraControl::Pan,
+                ControlValueSetter::Point(-0.5, 1.25),
+            ),
+            (
+                KnownCameraControl::Tilt,
+                ControlValueSetter::RGB(0.25, 0.5, 1.0),
+            ),
+            (
+                KnownCameraControl::Zoom,
+                ControlValueSetter::String("wide \"angle\"".to_string()),
+            ),
+            (
+                KnownCameraControl::Exposure,
+                ControlValueSetter::Bytes(vec![0, 127, 255]),
+            ),
+            (
+                KnownCameraControl::Iris,
+                ControlValueSetter::KeyValue(i128::MIN, i128::MAX),
+            ),
+            (KnownCameraControl::Focus, ControlValueSetter::None),
+            (
+                KnownCameraControl::Other(u128::MAX),
+                ControlValueSetter::Integer(i64::MAX),
+            ),
+        ]);
+        let preset = CameraPreset {
+            format: Some(CameraFormat::new(
+                Resolution::new(1280, 720),
+                FrameFormat::MJPEG,
+                30,
+            )),
+            controls,
+        };
+
+        PresetFile {
+            devices: BTreeMap::from([
+                (
+                    "usb-0000:00:14.0-5".to_string(),
+                    BTreeMap::from([
+                        ("day".to_string(), preset.clone()),
+                        ("night".to_string(), CameraPreset::default()),
+                    ]),
+                ),
+                (
+                    "Webcam".to_string(),
+                    BTreeMap::from([("day".to_string(), preset)]),
+                ),
+            ]),
+        }
+    }
+
+    fn round_trip(name: &str) -> String {
+        let file = TempFile::new(name);
+        let presets = CameraPresets {
+            path: file.0.clone(),
+            file: preset_file(),
+        };
+        presets.save().unwrap();
+
+        let loaded = CameraPresets::load(&file.0).unwrap();
+        assert_eq!(loaded.file, preset_file());
+        assert_eq!(loaded.path, file.0);
+        std::fs::read_to_string(&file.0).unwrap()
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let text = round_trip("presets.json");
+        assert!(text.starts_with('{'), "{text}");
+    }
+
+    #[test]
+    fn json_extension_ignores_case() {
+        let text = round_trip("presets.JSON");
+        assert!(text.starts_with('{'), "{text}");
+    }
+
+    #[test]
+    fn ron_round_trip() {
+        let text = round_trip("presets.ron");
+        assert!(text.starts_with('('), "{text}");
+        // anything but JSON is RON
+        let text = round_trip("presets");
+        assert!(text.starts_with('('), "{text}");
+    }
+
+    #[test]
+    fn load_reports_the_path() {
+        let file = TempFile::new("broken.json");
+        std::fs::write(&file.0, "{ not json").unwrap();
+        let err = CameraPresets::load(&file.0).unwrap_err();
+        assert!(err.to_string().contains("broken.json"), "{err}");
+    }
+
+    #[test]
+    fn device_key_prefers_the_unique_id() {
+        let with_id = CameraInfo::new("Webcam", "", "usb-1", CameraIndex::Index(0));
+        assert_eq!(device_key(&with_id), "usb-1");
+        let without_id = CameraInfo::new("Webcam", "", "", CameraIndex::Index(0));
+        assert_eq!(device_key(&without_id), "Webcam");
+    }
+
+    /// Reports one writable and one read-only control.
+    struct ControlledSource;
+
+    impl FrameSource for ControlledSource {
+        fn open(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn next_frame(&mut self, image: &mut RgbaImage) -> Result<()> {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            *image = RgbaImage::new(1, 1);
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn device(&mut self) -> Option<CameraDevice> {
+            Some(CameraDevice {
+                info: CameraInfo::new("Webcam", "", "usb-1", CameraIndex::Index(0)),
+                formats: Vec::new(),
+            })
+        }
+
+        fn controls(&self) -> Result<BTreeMap<KnownCameraControl, CameraControl>> {
+            let control = |id, flags| {
+                let description = ControlValueDescription::IntegerRange {
+                    min: 0,
+                    max: 10,
+                    value: 4,
+                    step: 1,
+                    default: 5,
+                };
+                (
+                    id,
+                    CameraControl::new(id, id.to_string(), description, flags, true),
+                )
+            };
+            Ok(BTreeMap::from([
+                control(KnownCameraControl::Brightness, vec![]),
+                control(
+                    KnownCameraControl::Gain,
+                    vec![KnownCameraControlFlag::ReadOnly],
+                ),
+            ]))
+        }
+    }
+
+    #[test]
+    fn capture_leaves_out_read_only_controls() {
+        let camera = BackgroundCamera::from_source(ControlledSource).unwrap();
+        let format = CameraFormat::new(Resolution::new(640, 480), FrameFormat::YUYV, 30);
+
+        let preset = CameraPreset::capture(&camera, Some(format));
+        assert_eq!(preset.format, Some(format));
+        assert_eq!(
+            preset.controls,
+            BTreeMap::from([(
+                KnownCameraControl::Brightness,
+                ControlValueSetter::Integer(4)
+            )])
+        );
+
+        // stored under the unique id of the device
+        let mut presets = CameraPresets {
+            path: PathBuf::new(),
+            file: PresetFile::default(),
+        };
+        presets.insert(&camera, "default", preset.clone());
+        assert_eq!(presets.file.devices["usb-1"]["default"], preset);
+        assert_eq!(presets.get(&camera, "default"), Some(&preset));
+        assert_eq!(presets.names(&camera).collect::<Vec<_>>(), ["default"]);
+    }
+}
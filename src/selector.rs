@@ -0,0 +1,149 @@
+use crate::source::TestPattern;
+use nokhwa::utils::{CameraIndex, CameraInfo, Resolution};
+use std::fmt;
+use std::sync::Arc;
+
+/// Which device a [`BackgroundCamera`](crate::camera::BackgroundCamera) should open.
+///
+/// Pass several to [`BackgroundCamera::select`](crate::camera::BackgroundCamera::select) to try
+/// them in order.
+#[derive(Clone)]
+pub enum CameraSelector {
+    /// The first device the backend reports.
+    Any,
+    Index(CameraIndex),
+    /// Exact human readable name.
+    Name(String),
+    /// Human readable name containing this, ignoring case.
+    NameContains(String),
+    #[cfg(feature = "regex")]
+    NameRegex(regex::Regex),
+    /// The backend specific unique id, see [`CameraInfo::misc`].
+    UniqueId(String),
+    Predicate(Arc<dyn Fn(&CameraInfo) -> bool + Send + Sync>),
+    /// Not a device, a synthetic source that always succeeds. Useful as the last fallback.
+    TestPattern {
+        pattern: TestPattern,
+        resolution: Resolution,
+        frame_rate: u32,
+    },
+}
+
+impl CameraSelector {
+    pub fn predicate(f: impl Fn(&CameraInfo) -> bool + Send + Sync + 'static) -> Self {
+        Self::Predicate(Arc::new(f))
+    }
+
+    pub fn matches(&self, info: &CameraInfo) -> bool {
+        match self {
+            CameraSelector::Any => true,
+            CameraSelector::Index(index) => info.index() == index,
+            CameraSelector::Name(name) => &info.human_name() == name,
+            CameraSelector::NameContains(part) => info
+                .human_name()
+                .to_lowercase()
+                .contains(&part.to_lowercase()),
+            #[cfg(feature = "regex")]
+            CameraSelector::NameRegex(regex) => regex.is_match(&info.human_name()),
+            CameraSelector::UniqueId(id) => &info.misc() == id,
+            CameraSelector::Predicate(f) => f(info),
+            CameraSelector::TestPattern { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for CameraSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraSelector::Any => write!(f, "any"),
+            CameraSelector::Index(index) => write!(f, "index {index}"),
+            CameraSelector::Name(name) => write!(f, "named {name:?}"),
+            CameraSelector::NameContains(part) => write!(f, "name containing {part:?}"),
+            #[cfg(feature = "regex")]
+            CameraSelector::NameRegex(regex) => write!(f, "name matching /{regex}/"),
+            CameraSelector::UniqueId(id) => write!(f, "unique id {id:?}"),
+            CameraSelector::Predicate(_) => write!(f, "matching predicate"),
+            CameraSelector::TestPattern { pattern, .. } => write!(f, "test pattern {pattern:?}"),
+        }
+    }
+}
+
+impl fmt::Debug for CameraSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CameraSelector({self})")
+    }
+}
+
+impl From<CameraIndex> for CameraSelector {
+    fn from(index: CameraIndex) -> Self {
+        Self::Index(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webcam() -> CameraInfo {
+        CameraInfo::new(
+            "Integrated Webcam",
+            "uvcvideo",
+            "usb-0000:00:14.0-5",
+            CameraIndex::Index(2),
+        )
+    }
+
+    #[test]
+    fn name_must_match_exactly() {
+        assert!(CameraSelector::Name("Integrated Webcam".to_string()).matches(&webcam()));
+        assert!(!CameraSelector::Name("integrated webcam".to_string()).matches(&webcam()));
+        assert!(!CameraSelector::Name("Webcam".to_string()).matches(&webcam()));
+    }
+
+    #[test]
+    fn name_contains_ignores_case() {
+        assert!(CameraSelector::NameContains("WEBCAM".to_string()).matches(&webcam()));
+        assert!(CameraSelector::NameContains("grated web".to_string()).matches(&webcam()));
+        assert!(!CameraSelector::NameContains("Logitech".to_string()).matches(&webcam()));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn name_regex_searches_the_name() {
+        let regex = regex::Regex::new("^Integrated").unwrap();
+        assert!(CameraSelector::NameRegex(regex).matches(&webcam()));
+        let regex = regex::Regex::new("^Webcam").unwrap();
+        assert!(!CameraSelector::NameRegex(regex).matches(&webcam()));
+    }
+
+    #[test]
+    fn unique_id_compares_misc() {
+        assert!(CameraSelector::UniqueId("usb-0000:00:14.0-5".to_string()).matches(&webcam()));
+        assert!(!CameraSelector::UniqueId("usb-0000:00:14.0-6".to_string()).matches(&webcam()));
+    }
+
+    #[test]
+    fn index_compares_the_index() {
+        assert!(CameraSelector::from(CameraIndex::Index(2)).matches(&webcam()));
+        assert!(!CameraSelector::Index(CameraIndex::Index(0)).matches(&webcam()));
+        assert!(!CameraSelector::Index(CameraIndex::String("2".to_string())).matches(&webcam()));
+    }
+
+    #[test]
+    fn predicate_sees_the_whole_info() {
+        let uvc = CameraSelector::predicate(|info| info.description() == "uvcvideo");
+        assert!(uvc.matches(&webcam()));
+        assert!(!CameraSelector::predicate(|_| false).matches(&webcam()));
+    }
+
+    #[test]
+    fn any_matches_and_test_pattern_never_does() {
+        assert!(CameraSelector::Any.matches(&webcam()));
+        let test_pattern = CameraSelector::TestPattern {
+            pattern: TestPattern::ColorBars,
+            resolution: Resolution::new(640, 480),
+            frame_rate: 30,
+        };
+        assert!(!test_pattern.matches(&webcam()));
+    }
+}
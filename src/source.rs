@@ -0,0 +1,256 @@
+use crate::camera::DeviceMatch;
+use crate::discovery::CameraDevice;
+use crate::error::{BevyNokhwaError, Result};
+use crate::selector::CameraSelector;
+use bevy::log::debug;
+use image::RgbaImage;
+use nokhwa::pixel_format::{RgbAFormat, RgbFormat};
+use nokhwa::utils::{
+    ApiBackend, CameraControl, CameraFormat, CameraIndex, CameraInfo, ControlValueSetter,
+    KnownCameraControl, RequestedFormat, RequestedFormatType,
+};
+use nokhwa::{nokhwa_initialize, query};
+use nokhwa::{Camera, NokhwaError};
+use std::collections::BTreeMap;
+
+pub use test_pattern::{TestPattern, TestPatternSource};
+
+mod test_pattern;
+
+/// Something that produces frames for a [`BackgroundCamera`](crate::camera::BackgroundCamera).
+///
+/// The source is moved onto the camera's worker thread, which calls [`FrameSource::open`] once,
+/// then [`FrameSource::next_frame`] in a loop until the camera is dropped.
+pub trait FrameSource: Send + 'static {
+    /// Start streaming.
+    fn open(&mut self) -> Result<()>;
+
+    /// Block until the next frame is available and write it to `image`.
+    ///
+    /// `image` is a recycled buffer that may still hold an older frame, resize it if the frame
+    /// size differs.
+    fn next_frame(&mut self, image: &mut RgbaImage) -> Result<()>;
+
+    /// The device behind this source and the formats it supports, if there is one.
+    fn device(&mut self) -> Option<CameraDevice> {
+        None
+    }
+
+    /// The format frames are captured in, if the source knows it.
+    fn format(&self) -> Option<CameraFormat> {
+        None
+    }
+
+    /// Stop the stream, switch to the format best matching `request` and restart the stream if
+    /// it was open. Returns the negotiated format.
+    fn set_format(&mut self, request: RequestedFormatType) -> Result<CameraFormat> {
+        Err(BevyNokhwaError::FormatUnsupported(
+            NokhwaError::SetPropertyError {
+                property: "format".to_string(),
+                value: request.to_string(),
+                error: "not supported by this source".to_string(),
+            },
+        ))
+    }
+
+    /// Controls supported by this source, with their current values.
+    fn controls(&self) -> Result<BTreeMap<KnownCameraControl, CameraControl>> {
+        Ok(BTreeMap::new())
+    }
+
+    /// Read a single control back from the device.
+    fn control(&self, id: KnownCameraControl) -> Result<CameraControl> {
+        self.controls()?
+            .remove(&id)
+            .ok_or_else(|| BevyNokhwaError::ControlRejected {
+                id,
+                error: NokhwaError::GetPropertyError {
+                    property: id.to_string(),
+                    error: "not supported by this source".to_string(),
+                },
+            })
+    }
+
+    fn set_control(&mut self, id: KnownCameraControl, control: ControlValueSetter) -> Result<()> {
+        Err(BevyNokhwaError::ControlRejected {
+            id,
+            error: NokhwaError::SetPropertyError {
+                property: id.to_string(),
+                value: control.to_string(),
+                error: "not supported by this source".to_string(),
+            },
+        })
+    }
+
+    /// Stop streaming and release the underlying device.
+    fn close(&mut self) -> Result<()>;
+
+    /// Find the device again after it went away and start streaming with the same format.
+    fn reconnect(&mut self, match_by: DeviceMatch) -> Result<()> {
+        let _ = match_by;
+        let _ = self.close();
+        self.open()
+    }
+}
+
+/// A physical camera device, opened through nokhwa.
+pub struct NokhwaSource {
+    api: ApiBackend,
+    info: CameraInfo,
+    camera: Camera,
+}
+
+impl NokhwaSource {
+    pub fn new(
+        api: ApiBackend,
+        index: Option<CameraIndex>,
+        request_format_type: Option<RequestedFormatType>,
+    ) -> Result<Self> {
+        let selector = index.map_or(CameraSelector::Any, CameraSelector::Index);
+        Self::select(api, &selector, request_format_type)
+    }
+
+    /// Open the first device matching `selector`.
+    ///
+    /// An explicit [`CameraSelector::Index`] the backend does not list, like a URL or a pipeline,
+    /// is opened directly.
+    pub fn select(
+        api: ApiBackend,
+        selector: &CameraSelector,
+        request_format_type: Option<RequestedFormatType>,
+    ) -> Result<Self> {
+        nokhwa_initialize(|granted| {
+            debug!("camera access granted: {granted}");
+        });
+        let cameras = match query(api) {
+            Ok(cameras) => cameras,
+            Err(err) if matches!(selector, CameraSelector::Index(_)) => {
+                debug!("could not list cameras: {err}");
+                Vec::new()
+            }
+            Err(err) => return Err(err.into()),
+        };
+        cameras
+            .iter()
+            .for_each(|cam| debug!("found camera {cam:?}"));
+
+        let format = RequestedFormat::new::<RgbFormat>(
+            request_format_type.unwrap_or(RequestedFormatType::AbsoluteHighestFrameRate),
+        );
+
+        let (info, camera) = match (cameras.iter().find(|cam| selector.matches(cam)), selector) {
+            (Some(info), _) => (info.clone(), open_camera(info.index().clone(), format)?),
+            (None, CameraSelector::Index(index)) => {
+                let camera = open_camera(index.clone(), format)?;
+                (camera.info().clone(), camera)
+            }
+            (None, _) if cameras.is_empty() => return Err(BevyNokhwaError::NoDevices),
+            (None, _) => return Err(BevyNokhwaError::DeviceNotFound(selector.to_string())),
+        };
+
+        Ok(Self { api, info, camera })
+    }
+}
+
+fn open_camera(index: CameraIndex, format: RequestedFormat) -> Result<Camera> {
+    Camera::new(index, format).map_err(|error| match error {
+        NokhwaError::GetPropertyError { .. } | NokhwaError::SetPropertyError { .. } => {
+            BevyNokhwaError::FormatUnsupported(error)
+        }
+        error => BevyNokhwaError::StreamOpenFailed(error),
+    })
+}
+
+impl FrameSource for NokhwaSource {
+    fn open(&mut self) -> Result<()> {
+        self.camera
+            .open_stream()
+            .map_err(BevyNokhwaError::StreamOpenFailed)
+    }
+
+    fn next_frame(&mut self, image: &mut RgbaImage) -> Result<()> {
+        let buffer = self
+            .camera
+            .frame()
+            .map_err(BevyNokhwaError::CaptureFailed)?;
+        let resolution = buffer.resolution();
+        if image.dimensions() != (resolution.width(), resolution.height()) {
+            *image = RgbaImage::new(resolution.width(), resolution.height());
+        }
+        buffer
+            .decode_image_to_buffer::<RgbAFormat>(image)
+            .map_err(BevyNokhwaError::DecodeFailed)
+    }
+
+    fn device(&mut self) -> Option<CameraDevice> {
+        Some(CameraDevice {
+            info: self.info.clone(),
+            formats: self.camera.compatible_camera_formats().unwrap_or_default(),
+        })
+    }
+
+    fn format(&self) -> Option<CameraFormat> {
+        Some(self.camera.camera_format())
+    }
+
+    fn set_format(&mut self, request: RequestedFormatType) -> Result<CameraFormat> {
+        let was_open = self.camera.is_stream_open();
+        if was_open {
+            self.camera.stop_stream()?;
+        }
+
+        let format = self
+            .camera
+            .set_camera_requset(RequestedFormat::new::<RgbFormat>(request))
+            .map_err(BevyNokhwaError::FormatUnsupported);
+
+        // the old format is kept if the new one was rejected
+        if was_open && !self.camera.is_stream_open() {
+            self.open()?;
+        }
+        format
+    }
+
+    fn controls(&self) -> Result<BTreeMap<KnownCameraControl, CameraControl>> {
+        Ok(self
+            .camera
+            .camera_controls_known_camera_controls()?
+            .into_iter()
+            .collect())
+    }
+
+    fn control(&self, id: KnownCameraControl) -> Result<CameraControl> {
+        Ok(self.camera.camera_control(id)?)
+    }
+
+    fn set_control(&mut self, id: KnownCameraControl, control: ControlValueSetter) -> Result<()> {
+        self.camera
+            .set_camera_control(id, control)
+            .map_err(|error| BevyNokhwaError::ControlRejected { id, error })
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.camera.stop_stream()?;
+        Ok(())
+    }
+
+    fn reconnect(&mut self, match_by: DeviceMatch) -> Result<()> {
+        let _ = self.camera.stop_stream();
+
+        let info = query(self.api)?
+            .into_iter()
+            .find(|cam| match match_by {
+                DeviceMatch::Name => cam.human_name() == self.info.human_name(),
+                DeviceMatch::UniqueId => cam.misc() == self.info.misc(),
+            })
+            .ok_or_else(|| BevyNokhwaError::DeviceNotFound(self.info.human_name()))?;
+
+        let format = RequestedFormat::new::<RgbFormat>(RequestedFormatType::Closest(
+            self.camera.camera_format(),
+        ));
+        self.camera = open_camera(info.index().clone(), format)?;
+        self.info = info;
+
+        self.open()
+    }
+}
@@ -0,0 +1,410 @@
+use crate::discovery::CameraDevice;
+use crate::error::{BevyNokhwaError, Result};
+use crate::source::FrameSource;
+use image::{Rgba, RgbaImage};
+use nokhwa::utils::{
+    CameraFormat, CameraIndex, CameraInfo, FrameFormat, RequestedFormatType, Resolution,
+};
+use nokhwa::NokhwaError;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum TestPattern {
+    /// SMPTE color bars.
+    #[default]
+    ColorBars,
+    /// A gradient scrolling horizontally one step per frame.
+    Gradient,
+    /// A checkerboard scrolling diagonally one pixel per frame.
+    Checkerboard,
+}
+
+/// A synthetic source for machines without a camera attached.
+///
+/// Every frame has its frame counter and the time since [`FrameSource::open`] burned into the
+/// top-left corner.
+pub struct TestPatternSource {
+    pattern: TestPattern,
+    resolution: Resolution,
+    frame_rate: u32,
+    frame: u64,
+    started: Option<Instant>,
+}
+
+impl TestPatternSource {
+    /// A zero width, height or frame rate is raised to 1.
+    pub fn new(pattern: TestPattern, resolution: Resolution, frame_rate: u32) -> Self {
+        Self {
+            pattern,
+            resolution: Resolution::new(resolution.width().max(1), resolution.height().max(1)),
+            frame_rate: frame_rate.max(1),
+            frame: 0,
+            started: None,
+        }
+    }
+
+    fn render(&self, image: &mut RgbaImage, elapsed: Duration) {
+        let (width, height) = (self.resolution.width(), self.resolution.height());
+        if image.dimensions() != (width, height) {
+            *image = RgbaImage::new(width, height);
+        }
+        match self.pattern {
+            TestPattern::ColorBars => color_bars(image),
+            TestPattern::Gradient => gradient(image, self.frame),
+            TestPattern::Checkerboard => checkerboard(image, self.frame),
+        }
+
+        let millis = elapsed.as_millis();
+        let text = format!(
+            "{:06} {:02}:{:02}.{:03}",
+            self.frame % 1_000_000,
+            millis / 60_000 % 100,
+            millis / 1000 % 60,
+            millis % 1000
+        );
+        burn_text(image, &text, (height / 120).max(1));
+    }
+}
+
+impl Default for TestPatternSource {
+    fn default() -> Self {
+        Self::new(TestPattern::default(), Resolution::new(640, 480), 30)
+    }
+}
+
+impl FrameSource for TestPatternSource {
+    fn open(&mut self) -> Result<()> {
+        self.frame = 0;
+        self.started = Some(Instant::now());
+        Ok(())
+    }
+
+    fn next_frame(&mut self, image: &mut RgbaImage) -> Result<()> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let deadline = started + Duration::from_secs(self.frame) / self.frame_rate;
+        let now = Instant::now();
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+        }
+
+        self.render(image, deadline - started);
+        self.frame += 1;
+
+        Ok(())
+    }
+
+    /// Lists the current format only, although any other one can be rendered as well.
+    fn device(&mut self) -> Option<CameraDevice> {
+        Some(CameraDevice {
+            info: CameraInfo::new(
+                "Test Pattern",
+                &format!("{:?}", self.pattern),
+                "",
+                CameraIndex::Index(0),
+            ),
+            formats: self.format().into_iter().collect(),
+        })
+    }
+
+    fn format(&self) -> Option<CameraFormat> {
+        Some(CameraFormat::new(
+            self.resolution,
+            FrameFormat::RAWRGB,
+            self.frame_rate,
+        ))
+    }
+
+    /// Any resolution and frame rate can be rendered, only an [`RequestedFormatType::Exact`]
+    /// request for another frame format than [`FrameFormat::RAWRGB`] is rejected.
+    fn set_format(&mut self, request: RequestedFormatType) -> Result<CameraFormat> {
+        let (resolution, frame_rate) = match request {
+            RequestedFormatType::Exact(format) if format.format() != FrameFormat::RAWRGB => {
+                return Err(unsupported(request));
+            }
+            RequestedFormatType::Exact(format) | RequestedFormatType::Closest(format) => {
+                (format.resolution(), format.frame_rate())
+            }
+            RequestedFormatType::HighestResolution(resolution) => (resolution, self.frame_rate),
+            RequestedFormatType::HighestFrameRate(frame_rate) => (self.resolution, frame_rate),
+            RequestedFormatType::AbsoluteHighestResolution
+            | RequestedFormatType::AbsoluteHighestFrameRate
+            | RequestedFormatType::None => (self.resolution, self.frame_rate),
+        };
+        if resolution.width() == 0 || resolution.height() == 0 || frame_rate == 0 {
+            return Err(unsupported(request));
+        }
+
+        self.resolution = resolution;
+        self.frame_rate = frame_rate;
+        // frames are paced from the start of the stream, restart it at the new rate
+        if self.started.is_some() {
+            self.open()?;
+        }
+
+        Ok(CameraFormat::new(
+            resolution,
+            FrameFormat::RAWRGB,
+            frame_rate,
+        ))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.started = None;
+        Ok(())
+    }
+}
+
+fn unsupported(request: RequestedFormatType) -> BevyNokhwaError {
+    BevyNokhwaError::FormatUnsupported(NokhwaError::SetPropertyError {
+        property: "format".to_string(),
+        value: request.to_string(),
+        error: "the test pattern is only rendered as RAWRGB".to_string(),
+    })
+}
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+fn fill(image: &mut RgbaImage, f: impl Fn(u32, u32) -> Rgba<u8>) {
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        *pixel = f(x, y);
+    }
+}
+
+fn color_bars(image: &mut RgbaImage) {
+    let (width, height) = image.dimensions();
+    const TOP: [[u8; 3]; 7] = [
+        [191, 191, 191],
+        [191, 191, 0],
+        [0, 191, 191],
+        [0, 191, 0],
+        [191, 0, 191],
+        [191, 0, 0],
+        [0, 0, 191],
+    ];
+    const MIDDLE: [[u8; 3]; 7] = [
+        [0, 0, 191],
+        [19, 19, 19],
+        [191, 0, 191],
+        [19, 19, 19],
+        [0, 191, 191],
+        [19, 19, 19],
+        [191, 191, 191],
+    ];
+    // -I, white, +Q, black, then the PLUGE steps below, at and above black.
+    const BOTTOM: [([u8; 3], u32); 7] = [
+        ([0, 33, 76], 5),
+        ([255, 255, 255], 5),
+        ([50, 0, 106], 5),
+        ([19, 19, 19], 5),
+        ([9, 9, 9], 2),
+        ([19, 19, 19], 2),
+        ([29, 29, 29], 2),
+    ];
+
+    fill(image, |x, y| {
+        // the bars are laid out on a 28 column grid
+        let column = x * 28 / width;
+        let [r, g, b] = if y < height * 2 / 3 {
+            TOP[(column / 4) as usize]
+        } else if y < height * 3 / 4 {
+            MIDDLE[(column / 4) as usize]
+        } else {
+            let mut end = 0;
+            BOTTOM
+                .iter()
+                .find(|(_, span)| {
+                    end += span;
+                    column < end
+                })
+                .map(|(color, _)| *color)
+                .unwrap_or([19, 19, 19])
+        };
+        Rgba([r, g, b, 255])
+    });
+}
+
+fn gradient(image: &mut RgbaImage, frame: u64) {
+    let (width, height) = image.dimensions();
+    let offset = (frame * 4 % width.max(1) as u64) as u32;
+    fill(image, |x, y| {
+        let u = ((x + offset) % width) * 255 / width.max(1);
+        let v = y * 255 / height.max(1);
+        Rgba([u as u8, v as u8, (255 - u) as u8, 255])
+    });
+}
+
+fn checkerboard(image: &mut RgbaImage, frame: u64) {
+    let size = (image.height() / 8).max(1);
+    let offset = (frame % (size as u64 * 2)) as u32;
+    fill(image, |x, y| {
+        if ((x + offset) / size + (y + offset) / size).is_multiple_of(2) {
+            WHITE
+        } else {
+            BLACK
+        }
+    });
+}
+
+/// 3x5 glyphs, one row per byte with the most significant of the low three bits on the left.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        _ => [0; 5],
+    }
+}
+
+fn burn_text(image: &mut RgbaImage, text: &str, scale: u32) {
+    let margin = 2 * scale;
+    let advance = 4 * scale;
+    let box_width = text.chars().count() as u32 * advance + 2 * margin;
+    let box_height = 5 * scale + 2 * margin;
+
+    for y in 0..box_height.min(image.height()) {
+        for x in 0..box_width.min(image.width()) {
+            image.put_pixel(x, y, BLACK);
+        }
+    }
+
+    for (i, c) in text.chars().enumerate() {
+        let origin_x = margin + i as u32 * advance;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = origin_x + col * scale + dx;
+                        let y = margin + row as u32 * scale + dy;
+                        if x < image.width() && y < image.height() {
+                            image.put_pixel(x, y, WHITE);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &mut TestPatternSource, image: &mut RgbaImage) {
+        source.open().unwrap();
+        source.next_frame(image).unwrap();
+    }
+
+    #[test]
+    fn frames_are_resized_to_the_format() {
+        let mut source = TestPatternSource::new(TestPattern::Gradient, Resolution::new(16, 8), 60);
+        // a pooled buffer left over from another format
+        let mut image = RgbaImage::new(3, 30);
+        render(&mut source, &mut image);
+        assert_eq!(image.dimensions(), (16, 8));
+
+        let format = CameraFormat::new(Resolution::new(4, 2), FrameFormat::RAWRGB, 60);
+        source
+            .set_format(RequestedFormatType::Closest(format))
+            .unwrap();
+        source.next_frame(&mut image).unwrap();
+        assert_eq!(image.dimensions(), (4, 2));
+    }
+
+    #[test]
+    fn set_format_rejects_what_cannot_be_rendered() {
+        let mut source = TestPatternSource::default();
+        let mjpeg = CameraFormat::new(Resolution::new(320, 240), FrameFormat::MJPEG, 30);
+        assert!(source
+            .set_format(RequestedFormatType::Exact(mjpeg))
+            .is_err());
+        // the closest one is rendered as RGB instead
+        assert_eq!(
+            source
+                .set_format(RequestedFormatType::Closest(mjpeg))
+                .unwrap(),
+            CameraFormat::new(Resolution::new(320, 240), FrameFormat::RAWRGB, 30)
+        );
+
+        let empty = CameraFormat::new(Resolution::new(0, 240), FrameFormat::RAWRGB, 30);
+        assert!(source
+            .set_format(RequestedFormatType::Closest(empty))
+            .is_err());
+        assert!(source
+            .set_format(RequestedFormatType::HighestFrameRate(0))
+            .is_err());
+        // rejected requests keep the format
+        assert_eq!(
+            source.format().unwrap().resolution(),
+            Resolution::new(320, 240)
+        );
+    }
+
+    #[test]
+    fn zero_sizes_are_raised_to_one() {
+        let source = TestPatternSource::new(TestPattern::ColorBars, Resolution::new(0, 0), 0);
+        assert_eq!(
+            source.format(),
+            Some(CameraFormat::new(
+                Resolution::new(1, 1),
+                FrameFormat::RAWRGB,
+                1
+            ))
+        );
+    }
+
+    #[test]
+    fn counter_stays_inside_tiny_frames() {
+        for pattern in [
+            TestPattern::ColorBars,
+            TestPattern::Gradient,
+            TestPattern::Checkerboard,
+        ] {
+            let mut source = TestPatternSource::new(pattern, Resolution::new(1, 1), 60);
+            let mut image = RgbaImage::new(0, 0);
+            render(&mut source, &mut image);
+            // covered by the black box behind the counter
+            assert_eq!(image.dimensions(), (1, 1));
+            assert_eq!(*image.get_pixel(0, 0), BLACK);
+        }
+    }
+
+    #[test]
+    fn color_bars_follow_the_smpte_layout() {
+        // 10 pixels per grid column, the counter only covers the top 9 rows
+        let mut source =
+            TestPatternSource::new(TestPattern::ColorBars, Resolution::new(280, 120), 60);
+        let mut image = RgbaImage::new(280, 120);
+        render(&mut source, &mut image);
+
+        let color = |x, y| {
+            let Rgba([r, g, b, _]) = *image.get_pixel(x, y);
+            [r, g, b]
+        };
+        // top: gray, yellow, ..., blue
+        assert_eq!(color(5, 50), [191, 191, 191]);
+        assert_eq!(color(45, 50), [191, 191, 0]);
+        assert_eq!(color(275, 50), [0, 0, 191]);
+        // middle: blue, black, magenta
+        assert_eq!(color(5, 85), [0, 0, 191]);
+        assert_eq!(color(45, 85), [19, 19, 19]);
+        assert_eq!(color(85, 85), [191, 0, 191]);
+        // bottom: -I, white, +Q, then the PLUGE steps
+        assert_eq!(color(5, 110), [0, 33, 76]);
+        assert_eq!(color(55, 110), [255, 255, 255]);
+        assert_eq!(color(205, 110), [9, 9, 9]);
+        assert_eq!(color(245, 110), [29, 29, 29]);
+    }
+}
@@ -0,0 +1,561 @@
+use crate::camera::{CameraOperation, ReconnectPolicy};
+use crate::discovery::CameraDevice;
+use crate::error::BevyNokhwaError;
+use crate::event::ControlOutcome;
+use crate::frame::{CameraFrame, DropPolicy, FrameCounters, FramePool, FrameQueue};
+use crate::source::FrameSource;
+use flume::{Receiver, RecvTimeoutError, Sender};
+use nokhwa::utils::{
+    CameraControl, CameraFormat, ControlValueSetter, KnownCameraControl, RequestedFormatType,
+};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often a worker blocked on a full [`FrameQueue`] checks whether the camera is still there.
+const BLOCK_POLL: Duration = Duration::from_millis(5);
+
+/// Messages from a camera's worker thread to the main world.
+pub(crate) enum WorkerEvent {
+    Opened,
+    FormatChanged(CameraFormat),
+    /// The device was reopened or switched format, and may list other formats or be another unit.
+    DeviceChanged(CameraDevice),
+    StreamStarted,
+    FrameDropped,
+    Paused,
+    Disconnected,
+    /// A control was set, `current` is what the device reports afterwards if it could be read.
+    Control {
+        id: KnownCameraControl,
+        value: ControlValueSetter,
+        outcome: ControlOutcome,
+        current: Option<Box<CameraControl>>,
+    },
+    /// Something went wrong, but the worker keeps running.
+    Error(BevyNokhwaError),
+    /// The worker stopped because of this error.
+    Failed(BevyNokhwaError),
+}
+
+pub(crate) struct Worker<S> {
+    source: S,
+    frame_tx: Sender<CameraFrame>,
+    /// Our end of the frame channel, to evict queued frames with [`DropPolicy::KeepNewest`].
+    frame_rx: Receiver<CameraFrame>,
+    op_rx: Receiver<CameraOperation>,
+    event_tx: Sender<WorkerEvent>,
+    queue: FrameQueue,
+    counters: Arc<FrameCounters>,
+    reconnect: Option<ReconnectPolicy>,
+    /// Last value successfully sent for each control, re-applied after reconnecting.
+    controls: BTreeMap<KnownCameraControl, ControlValueSetter>,
+    /// Format requested while reconnecting, applied once the device is back.
+    pending_format: Option<RequestedFormatType>,
+    /// Whether the last pause or resume requested while reconnecting was a pause.
+    pending_pause: bool,
+    pool: FramePool,
+    sequence: u64,
+    streaming: bool,
+    paused: bool,
+}
+
+impl<S: FrameSource> Worker<S> {
+    pub fn new(
+        source: S,
+        (frame_tx, frame_rx): (Sender<CameraFrame>, Receiver<CameraFrame>),
+        op_rx: Receiver<CameraOperation>,
+        event_tx: Sender<WorkerEvent>,
+        counters: Arc<FrameCounters>,
+    ) -> Self {
+        Self {
+            source,
+            frame_tx,
+            frame_rx,
+            op_rx,
+            event_tx,
+            queue: FrameQueue::default(),
+            counters,
+            reconnect: None,
+            controls: BTreeMap::new(),
+            pending_format: None,
+            pending_pause: false,
+            pool: FramePool::default(),
+            sequence: 0,
+            streaming: false,
+            paused: false,
+        }
+    }
+
+    /// Start the thread, the returned receiver disconnects once it exited.
+    pub fn spawn(self) -> (JoinHandle<()>, Receiver<()>) {
+        let (done_tx, done_rx) = flume::bounded(0);
+        let thread = std::thread::spawn(move || {
+            // dropped however the thread ends, panics included
+            let _done: Sender<()> = done_tx;
+            self.run()
+        });
+        (thread, done_rx)
+    }
+
+    fn run(mut self) {
+        loop {
+            let first = if self.paused {
+                // nothing to capture, wait for someone to resume us
+                match self.op_rx.recv() {
+                    Ok(op) => Some(op),
+                    Err(_) => break,
+                }
+            } else {
+                None
+            };
+
+            // only the latest value of each control is worth sending to the device
+            let mut controls = BTreeMap::new();
+            let ops: Vec<_> = first.into_iter().chain(self.op_rx.try_iter()).collect();
+            let mut stop = false;
+            for op in ops {
+                match op {
+                    CameraOperation::Control { id, control } => {
+                        controls.insert(id, control);
+                    }
+                    op => {
+                        if !self.handle(op) {
+                            stop = true;
+                            break;
+                        }
+                    }
+                }
+            }
+            if stop {
+                break;
+            }
+            for (id, control) in controls {
+                self.apply_control(id, control);
+            }
+
+            if self.paused {
+                continue;
+            }
+
+            let mut image = self.pool.take();
+            match self.source.next_frame(&mut image) {
+                Ok(()) => {
+                    if !self.streaming {
+                        self.streaming = true;
+                        self.send(WorkerEvent::StreamStarted);
+                    }
+                    self.sequence += 1;
+                    let frame = CameraFrame {
+                        image: self.pool.share(image),
+                        sequence: self.sequence,
+                        captured_at: Instant::now(),
+                    };
+                    if !self.deliver(frame) {
+                        break;
+                    }
+                }
+                // a single corrupt frame is not fatal, skip it
+                Err(err @ BevyNokhwaError::DecodeFailed(_)) => {
+                    self.pool.recycle(image);
+                    self.send(WorkerEvent::FrameDropped);
+                    self.send(WorkerEvent::Error(err));
+                }
+                Err(err @ BevyNokhwaError::CaptureFailed(_)) => {
+                    self.streaming = false;
+                    self.send(WorkerEvent::Disconnected);
+                    if self.reconnect.is_none() {
+                        self.send(WorkerEvent::Failed(err));
+                        break;
+                    }
+                    if !self.reconnect(err) {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    self.send(WorkerEvent::Failed(err));
+                    break;
+                }
+            }
+        }
+
+        if !self.paused {
+            if let Err(err) = self.source.close() {
+                self.send(WorkerEvent::Error(err));
+            }
+        }
+    }
+
+    /// Apply an operation, returns `false` if the worker has to stop.
+    ///
+    /// The source is left closed if we were paused, and open otherwise.
+    fn handle(&mut self, op: CameraOperation) -> bool {
+        match op {
+            CameraOperation::Control { id, control } => self.apply_control(id, control),
+            CameraOperation::SetFormat(request) => match self.source.set_format(request) {
+                Ok(format) => {
+                    self.streaming = false;
+                    self.send(WorkerEvent::FormatChanged(format));
+                    self.send_device();
+                }
+                Err(err @ BevyNokhwaError::StreamOpenFailed(_)) if !self.paused => {
+                    self.send(WorkerEvent::Failed(err));
+                    return false;
+                }
+                Err(err) => self.send(WorkerEvent::Error(err)),
+            },
+            CameraOperation::Pause if !self.paused => {
+                if let Err(err) = self.source.close() {
+                    self.send(WorkerEvent::Error(err));
+                }
+                self.paused = true;
+                self.streaming = false;
+                self.send(WorkerEvent::Paused);
+            }
+            CameraOperation::Resume if self.paused => {
+                if let Err(err) = self.source.open() {
+                    self.send(WorkerEvent::Failed(err));
+                    return false;
+                }
+                self.paused = false;
+                self.send(WorkerEvent::Opened);
+            }
+            CameraOperation::SetReconnect(policy) => self.reconnect = policy,
+            CameraOperation::SetFrameQueue(queue) => self.queue = queue,
+            CameraOperation::Stop => return false,
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Queue a frame for the app according to the [`FrameQueue`], returns `false` once the camera
+    /// was dropped.
+    fn deliver(&mut self, frame: CameraFrame) -> bool {
+        self.counters.produced();
+
+        loop {
+            // the receiver we hold ourselves does not count
+            if self.frame_tx.receiver_count() <= 1 {
+                return false;
+            }
+            if self.frame_tx.len() < self.queue.depth.max(1) {
+                break;
+            }
+
+            match self.queue.policy {
+                DropPolicy::KeepNewest => {
+                    if self.frame_rx.try_recv().is_ok() {
+                        self.counters.dropped(1);
+                    }
+                }
+                DropPolicy::KeepOldest => {
+                    self.counters.dropped(1);
+                    return true;
+                }
+                DropPolicy::Block => match self.op_rx.recv_timeout(BLOCK_POLL) {
+                    Ok(op) => {
+                        if !self.handle(op) {
+                            return false;
+                        }
+                        if self.paused {
+                            self.counters.dropped(1);
+                            return true;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return false,
+                },
+            }
+        }
+
+        self.frame_tx.send(frame).is_ok()
+    }
+
+    /// Keep trying to reopen the source, returns `false` if we gave up or the camera was dropped.
+    ///
+    /// The policy is read again before every attempt, so it can be changed while we wait.
+    fn reconnect(&mut self, mut last_error: BevyNokhwaError) -> bool {
+        let mut attempt = 0;
+        loop {
+            let Some(policy) = self.reconnect.clone() else {
+                self.send(WorkerEvent::Failed(last_error));
+                return false;
+            };
+            if policy.max_attempts.is_some_and(|max| attempt >= max) {
+                self.send(WorkerEvent::Failed(last_error));
+                return false;
+            }
+
+            // keep taking operations while waiting, controls sent meanwhile are applied once
+            // the device is back
+            let deadline = Instant::now() + policy.delay(attempt);
+            loop {
+                match self.op_rx.recv_deadline(deadline) {
+                    Ok(CameraOperation::Control { id, control }) => {
+                        self.controls.insert(id, control);
+                    }
+                    Ok(CameraOperation::SetReconnect(None)) => {
+                        self.reconnect = None;
+                        self.send(WorkerEvent::Failed(last_error));
+                        return false;
+                    }
+                    Ok(CameraOperation::SetReconnect(policy)) => self.reconnect = policy,
+                    Ok(CameraOperation::SetFrameQueue(queue)) => self.queue = queue,
+                    Ok(CameraOperation::SetFormat(request)) => self.pending_format = Some(request),
+                    Ok(CameraOperation::Pause) => self.pending_pause = true,
+                    Ok(CameraOperation::Resume) => self.pending_pause = false,
+                    Ok(CameraOperation::Stop) => return false,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return false,
+                }
+            }
+
+            attempt += 1;
+            match self.source.reconnect(policy.match_by) {
+                Ok(()) => {
+                    for (id, control) in self.controls.clone() {
+                        self.apply_control(id, control);
+                    }
+                    self.send(WorkerEvent::Opened);
+                    match self.pending_format.take() {
+                        Some(request) => {
+                            if !self.handle(CameraOperation::SetFormat(request)) {
+                                return false;
+                            }
+                        }
+                        None => {
+                            if let Some(format) = self.source.format() {
+                                self.send(WorkerEvent::FormatChanged(format));
+                            }
+                            self.send_device();
+                        }
+                    }
+                    if std::mem::take(&mut self.pending_pause) {
+                        return self.handle(CameraOperation::Pause);
+                    }
+                    return true;
+                }
+                Err(err) => last_error = err,
+            }
+        }
+    }
+
+    /// Set a control and report how the device took it.
+    fn apply_control(&mut self, id: KnownCameraControl, value: ControlValueSetter) {
+        let outcome = match self.source.set_control(id, value.clone()) {
+            Ok(()) => {
+                self.controls.insert(id, value.clone());
+                ControlOutcome::Applied
+            }
+            Err(err) => ControlOutcome::Rejected(err),
+        };
+
+        let current = self.source.control(id).ok();
+        let outcome = match (outcome, &current) {
+            (ControlOutcome::Applied, Some(current)) if current.value() != value => {
+                ControlOutcome::Clamped(current.value())
+            }
+            (outcome, _) => outcome,
+        };
+
+        self.send(WorkerEvent::Control {
+            id,
+            value,
+            outcome,
+            current: current.map(Box::new),
+        });
+    }
+
+    fn send(&self, event: WorkerEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn send_device(&mut self) {
+        if let Some(device) = self.source.device() {
+            self.send(WorkerEvent::DeviceChanged(device));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WorkerEvent;
+    use crate::camera::BackgroundCamera;
+    use crate::error::{BevyNokhwaError, Result};
+    use crate::frame::{DropPolicy, FrameQueue, FrameStats};
+    use crate::source::{FrameSource, TestPattern, TestPatternSource};
+    use flume::{Receiver, Sender};
+    use image::RgbaImage;
+    use nokhwa::utils::{CameraFormat, FrameFormat, RequestedFormatType, Resolution};
+    use nokhwa::NokhwaError;
+    use std::time::{Duration, Instant};
+
+    /// How long to wait for the worker before a test fails.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Captures a frame whenever the test sends a step, so no test depends on timing.
+    struct SteppedSource {
+        pattern: TestPatternSource,
+        steps: Receiver<()>,
+    }
+
+    impl FrameSource for SteppedSource {
+        fn open(&mut self) -> Result<()> {
+            self.pattern.open()
+        }
+
+        fn next_frame(&mut self, image: &mut RgbaImage) -> Result<()> {
+            // the test is done once it drops the sender
+            self.steps.recv().map_err(|_| {
+                BevyNokhwaError::CaptureFailed(NokhwaError::ReadFrameError("done".to_string()))
+            })?;
+            self.pattern.next_frame(image)
+        }
+
+        fn close(&mut self) -> Result<()> {
+            self.pattern.close()
+        }
+    }
+
+    struct Stepped {
+        camera: BackgroundCamera,
+        steps: Sender<()>,
+    }
+
+    impl Stepped {
+        fn new(policy: DropPolicy) -> Self {
+            let (steps, step_rx) = flume::unbounded();
+            let source = SteppedSource {
+                pattern: TestPatternSource::new(
+                    TestPattern::ColorBars,
+                    Resolution::new(4, 4),
+                    1000,
+                ),
+                steps: step_rx,
+            };
+            // the first frame may still go out with the default queue, it fits either way
+            let camera = BackgroundCamera::from_source(source)
+                .unwrap()
+                .with_frame_queue(FrameQueue { depth: 1, policy });
+            Self { camera, steps }
+        }
+
+        /// Capture `frames` frames and wait until each was queued or dropped.
+        fn capture(&self, frames: u64) {
+            for _ in 0..frames {
+                let produced = self.stats().produced + 1;
+                self.steps.send(()).unwrap();
+                self.wait_until(|stats, queued| {
+                    stats.produced == produced
+                        && stats.consumed + stats.dropped + queued == produced
+                });
+            }
+        }
+
+        fn wait_until(&self, done: impl Fn(FrameStats, u64) -> bool) {
+            let deadline = Instant::now() + TIMEOUT;
+            while !done(self.stats(), self.camera.image_rx.len() as u64) {
+                assert!(Instant::now() < deadline, "stuck at {:?}", self.stats());
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        /// Take a frame the way the app does, returns its sequence number.
+        fn take(&self) -> u64 {
+            let frame = self.camera.image_rx.try_recv().expect("a frame is queued");
+            self.camera.frame_counters.consumed();
+            frame.sequence
+        }
+
+        fn queued(&self) -> Vec<u64> {
+            self.camera
+                .image_rx
+                .try_iter()
+                .map(|frame| frame.sequence)
+                .collect()
+        }
+
+        fn stats(&self) -> FrameStats {
+            self.camera.frame_stats()
+        }
+    }
+
+    impl Drop for Stepped {
+        fn drop(&mut self) {
+            // let the worker out of `next_frame`, so stopping does not have to wait for it
+            let (steps, _) = flume::bounded(0);
+            drop(std::mem::replace(&mut self.steps, steps));
+            self.camera.stop();
+        }
+    }
+
+    fn stats(produced: u64, consumed: u64, dropped: u64) -> FrameStats {
+        FrameStats {
+            produced,
+            consumed,
+            dropped,
+        }
+    }
+
+    #[test]
+    fn keep_newest_replaces_the_queued_frame() {
+        let stepped = Stepped::new(DropPolicy::KeepNewest);
+        stepped.capture(3);
+        assert_eq!(stepped.stats(), stats(3, 0, 2));
+        assert_eq!(stepped.take(), 3);
+
+        stepped.capture(2);
+        assert_eq!(stepped.stats(), stats(5, 1, 3));
+        assert_eq!(stepped.queued(), [5]);
+    }
+
+    #[test]
+    fn keep_oldest_drops_new_frames() {
+        let stepped = Stepped::new(DropPolicy::KeepOldest);
+        stepped.capture(3);
+        assert_eq!(stepped.stats(), stats(3, 0, 2));
+        assert_eq!(stepped.take(), 1);
+
+        // the first frame captured after the queue emptied is kept
+        stepped.capture(2);
+        assert_eq!(stepped.stats(), stats(5, 1, 3));
+        assert_eq!(stepped.queued(), [4]);
+    }
+
+    #[test]
+    fn block_holds_frames_back_until_taken() {
+        let stepped = Stepped::new(DropPolicy::Block);
+        stepped.capture(1);
+        stepped.steps.send(()).unwrap();
+        stepped.steps.send(()).unwrap();
+        // the second frame waits for room in the queue, the third is not captured yet
+        stepped.wait_until(|stats, _| stats.produced == 2);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(stepped.stats(), stats(2, 0, 0));
+        assert_eq!(stepped.take(), 1);
+
+        stepped.wait_until(|stats, _| stats.produced == 3);
+        assert_eq!(stepped.take(), 2);
+        stepped.wait_until(|_, queued| queued == 1);
+        assert_eq!(stepped.stats(), stats(3, 2, 0));
+        assert_eq!(stepped.queued(), [3]);
+    }
+
+    #[test]
+    fn format_change_reports_the_device() {
+        let camera =
+            BackgroundCamera::test_pattern(TestPattern::ColorBars, Resolution::new(4, 4), 100);
+        let format = CameraFormat::new(Resolution::new(8, 6), FrameFormat::RAWRGB, 100);
+        camera.set_format(RequestedFormatType::Exact(format));
+
+        let device = std::iter::from_fn(|| camera.event_rx.recv_timeout(TIMEOUT).ok())
+            .find_map(|event| match event {
+                WorkerEvent::DeviceChanged(device) => Some(device),
+                _ => None,
+            })
+            .expect("the device is sent again");
+        assert_eq!(device.formats, [format]);
+    }
+}